@@ -0,0 +1,178 @@
+use crate::{
+    config::RepoConfig,
+    notifiers_for,
+    notify::{Notifier, NtfyMessage},
+    resolve_repos, state, Args, Exit,
+};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use std::{
+    io::{self, BufRead, Write},
+    net::SocketAddr,
+};
+use tracing::{error, info};
+
+const TEST_MESSAGE: &str = "✅ dockmasterbot test message";
+
+#[derive(clap::Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Send a test message to every configured target and exit, without
+    /// touching GitHub or the state
+    TestNotify,
+    /// Print the tag and check time recorded for every repo in the state
+    ListState {
+        /// Print JSON instead of a table
+        #[arg(long)]
+        json: bool,
+    },
+    /// Forget the recorded tag of one repo, or of all repos, so it is
+    /// announced again on the next check
+    ResetState {
+        /// Only reset this owner/repo
+        #[arg(long)]
+        repo: Option<String>,
+        /// Don't ask before resetting every repo
+        #[arg(long)]
+        yes: bool,
+    },
+    /// Instead of polling, receive GitHub `release` and `create` webhooks
+    /// and announce them right away
+    Serve {
+        /// Address the webhook receiver listens on
+        #[arg(long, env = "LISTEN_ADDR", default_value = "0.0.0.0:8080")]
+        listen_addr: SocketAddr,
+        /// Secret configured on the GitHub webhook, used to check X-Hub-Signature-256
+        #[arg(long, env = "WEBHOOK_SECRET", hide_env_values = true)]
+        webhook_secret: String,
+    },
+}
+
+/// Sends [`TEST_MESSAGE`] to every distinct target; fails if any of them fails.
+pub async fn test_notify(args: &Args, client: &reqwest::Client) -> Result<()> {
+    let repos = resolve_repos(args).context(Exit::Config)?;
+    let mut notifiers: Vec<Notifier> = Vec::new();
+    if repos.is_empty() {
+        // no repos given, only the global targets can be tested
+        notifiers = notifiers_for(&RepoConfig::new(""), args);
+    }
+    for repo in repos.iter().flat_map(RepoConfig::with_channels) {
+        for notifier in notifiers_for(&repo, args) {
+            if !notifiers.iter().any(|n| n.to_string() == notifier.to_string()) {
+                notifiers.push(notifier);
+            }
+        }
+    }
+    if notifiers.is_empty() {
+        anyhow::bail!("no notification target configured");
+    }
+
+    let mut failed = 0;
+    for notifier in &notifiers {
+        let text = match notifier {
+            Notifier::Webhook { .. } => {
+                serde_json::json!({ "event": "test", "text": TEST_MESSAGE }).to_string()
+            }
+            Notifier::Teams { .. } => serde_json::json!({
+                "@type": "MessageCard",
+                "@context": "https://schema.org/extensions",
+                "summary": TEST_MESSAGE,
+                "text": TEST_MESSAGE,
+            })
+            .to_string(),
+            _ if notifier.takes_ntfy_message() => serde_json::to_string(&NtfyMessage {
+                title: "dockmasterbot".into(),
+                message: TEST_MESSAGE.into(),
+                click: String::new(),
+            })?,
+            // the first line becomes the subject
+            Notifier::Email { .. } => format!("dockmasterbot test message\n{TEST_MESSAGE}"),
+            _ => TEST_MESSAGE.to_string(),
+        };
+        match notifier.send(client, text, &[], args.dry_run).await {
+            Ok(()) => info!(target = %notifier, "test message sent"),
+            Err(e) => {
+                error!(target = %notifier, error=?e, "test message failed");
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        anyhow::bail!("{failed} of {} targets failed", notifiers.len());
+    }
+    Ok(())
+}
+
+/// Prints the state as a `repo | last_seen_tag | last_checked_at | last_error`
+/// table, or every field as JSON.
+pub fn list_state(args: &Args, json: bool) -> Result<()> {
+    let store = state::open(args.state_backend, &args.state_path, args.state_backup_count)
+        .context(Exit::State)?;
+    let repos = store.list()?;
+    if json {
+        let entries: Vec<_> = repos
+            .iter()
+            .map(|(repo, state)| {
+                let mut entry = serde_json::to_value(state).expect("repo state serializes");
+                entry["repo"] = repo.as_str().into();
+                entry
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&entries)?);
+        return Ok(());
+    }
+
+    let rows: Vec<[String; 4]> = repos
+        .into_iter()
+        .map(|(repo, state)| {
+            let checked = state.last_checked_at.as_ref().map(DateTime::<Utc>::to_rfc3339);
+            [
+                repo,
+                state.last_seen_tag.unwrap_or_else(|| "-".into()),
+                checked.unwrap_or_else(|| "-".into()),
+                state.last_error.unwrap_or_else(|| "-".into()),
+            ]
+        })
+        .collect();
+    let header = ["repo", "last_seen_tag", "last_checked_at", "last_error"].map(String::from);
+    let width = |i: usize| rows.iter().chain([&header]).map(|r| r[i].chars().count()).max();
+    let (w0, w1, w2) = (width(0).unwrap_or(0), width(1).unwrap_or(0), width(2).unwrap_or(0));
+    for [repo, tag, checked, error] in [&header].into_iter().chain(&rows) {
+        println!("{repo:w0$} | {tag:w1$} | {checked:w2$} | {error}");
+    }
+    Ok(())
+}
+
+/// Removes one repo (or, after confirmation, every repo) from the state and
+/// saves it.
+pub fn reset_state(args: &Args, repo: Option<&str>, yes: bool) -> Result<()> {
+    let _lock = state::lock(args.state_backend, &args.state_path).context(Exit::State)?;
+    let mut store = state::open(args.state_backend, &args.state_path, args.state_backup_count)
+        .context(Exit::State)?;
+    match repo {
+        Some(repo) => {
+            if !store.remove(repo)? {
+                anyhow::bail!("{repo} is not in the state");
+            }
+            info!(%repo, "state reset");
+        }
+        None => {
+            if !yes && !confirm("Reset the state of ALL repos?")? {
+                anyhow::bail!("aborted");
+            }
+            let count = store.clear()?;
+            info!(count, "state reset for all repos");
+        }
+    }
+    store.flush()
+}
+
+fn confirm(question: &str) -> Result<bool> {
+    print!("{question} [y/N] ");
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin()
+        .lock()
+        .read_line(&mut answer)
+        .context("reading confirmation")?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
@@ -0,0 +1,131 @@
+use anyhow::{Context, Result};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use serde::Deserialize;
+use std::{fs, path::Path};
+use tokio::sync::mpsc;
+
+/// Contents of the `--config` TOML file.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    pub repos: Vec<RepoConfig>,
+}
+
+/// A watched repo and its per-repo overrides.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RepoConfig {
+    /// owner/repo, or owner/* for every repo of owner
+    pub name: String,
+    /// Overrides the global --tg-chat-id for this repo
+    pub chat_id: Option<ChatIds>,
+    /// Overrides the global --tg-thread-id for this repo
+    pub thread_id: Option<i64>,
+    /// Overrides the global --poll-secs for this repo
+    pub poll_secs: Option<u64>,
+    /// Tags that parse as semver below this version are recorded but not announced
+    pub min_version: Option<semver::Version>,
+    /// Overrides the global --latest-pointer for this repo
+    pub latest_pointer: Option<bool>,
+    /// Only releases published by these GitHub logins are announced
+    pub allowed_authors: Option<Vec<String>>,
+    /// Where stable releases go instead of `chat_id`
+    pub stable_chat_id: Option<ChatIds>,
+    /// Where pre-releases go instead of `chat_id`
+    pub prerelease_chat_id: Option<ChatIds>,
+}
+
+/// Lets `chat_id` be written as a single id or a list of ids.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum ChatIds {
+    One(i64),
+    Many(Vec<i64>),
+}
+
+impl Config {
+    pub fn load(p: &Path) -> Result<Self> {
+        let s = fs::read_to_string(p)
+            .with_context(|| format!("reading config file {}", p.display()))?;
+        toml::from_str(&s).with_context(|| format!("parsing config file {}", p.display()))
+    }
+}
+
+/// Watches the config file `p` for --watch-config: the receiver gets a
+/// message whenever it's written, replaced or removed, for as long as the
+/// watcher is kept. Its directory is watched rather than the file itself, so
+/// editors that save by renaming a new file over it are noticed too.
+pub fn watch(p: &Path) -> Result<(RecommendedWatcher, mpsc::UnboundedReceiver<()>)> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let name = p.file_name().map(ToOwned::to_owned);
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+        let Ok(event) = res else { return };
+        let ours = event.paths.iter().any(|changed| changed.file_name() == name.as_deref());
+        if ours && !event.kind.is_access() {
+            let _ = tx.send(());
+        }
+    })
+    .context("starting the config file watcher")?;
+    let dir = p.parent().filter(|d| !d.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    watcher
+        .watch(dir, RecursiveMode::NonRecursive)
+        .with_context(|| format!("watching config dir {}", dir.display()))?;
+    Ok((watcher, rx))
+}
+
+impl RepoConfig {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            chat_id: None,
+            thread_id: None,
+            poll_secs: None,
+            min_version: None,
+            latest_pointer: None,
+            allowed_authors: None,
+            stable_chat_id: None,
+            prerelease_chat_id: None,
+        }
+    }
+
+    /// `owner` for an `owner/*` entry that stands for all of owner's repos.
+    pub fn wildcard_owner(&self) -> Option<&str> {
+        self.name.strip_suffix("/*")
+    }
+
+    /// Whether only the release marked "Latest" is followed, falling back to
+    /// `global` when not overridden.
+    pub fn latest_pointer(&self, global: bool) -> bool {
+        self.latest_pointer.unwrap_or(global)
+    }
+
+    /// How often this repo is checked, falling back to `global` when not overridden.
+    pub fn poll_secs(&self, global: u64) -> u64 {
+        self.poll_secs.unwrap_or(global)
+    }
+
+    /// Chat ids this repo is routed to, falling back to `global` when not overridden.
+    pub fn chat_ids<'a>(&'a self, global: &'a [i64]) -> &'a [i64] {
+        match &self.chat_id {
+            Some(ChatIds::One(id)) => std::slice::from_ref(id),
+            Some(ChatIds::Many(ids)) => ids,
+            None => global,
+        }
+    }
+
+    /// This entry sending to its stable or pre-release channel instead of
+    /// `chat_id`, if that channel is configured.
+    pub fn routed(&self, prerelease: bool) -> Option<RepoConfig> {
+        let ids = if prerelease { &self.prerelease_chat_id } else { &self.stable_chat_id };
+        ids.clone().map(|ids| RepoConfig {
+            chat_id: Some(ids),
+            ..self.clone()
+        })
+    }
+
+    /// This entry and its routed channels, to cover every chat it may send to.
+    pub fn with_channels(&self) -> Vec<RepoConfig> {
+        let mut entries = vec![self.clone()];
+        entries.extend(self.routed(false));
+        entries.extend(self.routed(true));
+        entries
+    }
+}
@@ -0,0 +1,133 @@
+use anyhow::{Context, Result};
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, StatusCode},
+    response::{Html, IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use chrono::{DateTime, Utc};
+use ring::hmac;
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    escape_html,
+    state::{self, RepoState, StateStore},
+};
+
+struct Dashboard {
+    state: Arc<Mutex<Box<dyn StateStore>>>,
+    /// The watched repos, kept current by the poll loop
+    repos: Arc<Mutex<Vec<String>>>,
+    /// Required as `Authorization: Bearer <token>` when set: a key made
+    /// from it and its tag, so a given token is checked in constant time
+    token: Option<(hmac::Key, hmac::Tag)>,
+}
+
+/// Serves a read-only HTML table of every watched repo and its state on
+/// `port` at `/` until the process exits, read afresh for every request.
+pub async fn serve(
+    port: u16,
+    token: Option<String>,
+    state: Arc<Mutex<Box<dyn StateStore>>>,
+    repos: Arc<Mutex<Vec<String>>>,
+) -> Result<()> {
+    let token = token.map(|token| {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, token.as_bytes());
+        let tag = hmac::sign(&key, token.as_bytes());
+        (key, tag)
+    });
+    let app = Router::new()
+        .route("/", get(index))
+        .with_state(Arc::new(Dashboard { state, repos, token }));
+    let listener = tokio::net::TcpListener::bind(("0.0.0.0", port))
+        .await
+        .with_context(|| format!("binding dashboard port {}", port))?;
+    axum::serve(listener, app).await.context("dashboard server")?;
+    Ok(())
+}
+
+async fn index(State(dashboard): State<Arc<Dashboard>>, headers: HeaderMap) -> Response {
+    if let Some((key, tag)) = &dashboard.token {
+        let given = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "));
+        let valid =
+            given.is_some_and(|given| hmac::verify(key, given.as_bytes(), tag.as_ref()).is_ok());
+        if !valid {
+            return StatusCode::UNAUTHORIZED.into_response();
+        }
+    }
+    let stored = dashboard.state.lock().expect("state lock poisoned").list();
+    match stored {
+        Ok(stored) => {
+            let watched = dashboard.repos.lock().expect("repo list lock poisoned").clone();
+            Html(render(&with_watched(stored, &watched))).into_response()
+        }
+        Err(e) => {
+            tracing::error!(error=?e, "reading the state for the dashboard failed");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// `stored` plus an empty row for each of `watched` that has no state yet,
+/// sorted by repo.
+fn with_watched(stored: Vec<(String, RepoState)>, watched: &[String]) -> Vec<(String, RepoState)> {
+    let mut repos: BTreeMap<String, RepoState> = stored.into_iter().collect();
+    for repo in watched {
+        repos.entry(repo.clone()).or_default();
+    }
+    repos.into_iter().collect()
+}
+
+fn render(repos: &[(String, RepoState)]) -> String {
+    let time = |t: Option<DateTime<Utc>>| {
+        t.map(|t| t.format("%Y-%m-%d %H:%M:%S UTC").to_string()).unwrap_or_else(|| "-".into())
+    };
+    let rows: String = repos
+        .iter()
+        .map(|(repo, r)| {
+            format!(
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+                escape_html(repo),
+                escape_html(r.last_seen_tag.as_deref().unwrap_or("-")),
+                r.kind.map(state::kind_name).unwrap_or("-"),
+                time(r.last_checked_at),
+                escape_html(r.last_error.as_deref().unwrap_or("")),
+            )
+        })
+        .collect();
+    format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>Watched repos</title>\
+         <style>body{{font-family:sans-serif}}td,th{{padding:2px 12px;text-align:left}}\
+         tr:nth-child(even){{background:#f2f2f2}}</style></head><body>\n\
+         <h1>Watched repos</h1>\n<table>\n\
+         <tr><th>Repo</th><th>Version</th><th>Kind</th><th>Last checked</th><th>Last error</th></tr>\n\
+         {rows}</table>\n</body></html>\n"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_escapes_every_row_and_lists_unchecked_repos() {
+        let state = RepoState {
+            last_seen_tag: Some("v1.0.0".into()),
+            last_error: Some("<404> Not Found".into()),
+            ..RepoState::default()
+        };
+        let repos = with_watched(vec![("o/r".into(), state)], &["o/new".into(), "o/r".into()]);
+        let html = render(&repos);
+        assert!(html.contains(
+            "<tr><td>o/new</td><td>-</td><td>-</td><td>-</td><td></td></tr>\n\
+             <tr><td>o/r</td><td>v1.0.0</td><td>-</td><td>-</td><td>&lt;404&gt; Not Found</td></tr>"
+        ));
+    }
+}
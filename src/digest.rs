@@ -0,0 +1,186 @@
+use chrono::Utc;
+use std::{collections::HashSet, sync::Mutex};
+use tracing::{error, info, warn};
+
+use crate::{
+    append_event, candidate_url, escape_for, metrics::METRICS, notify::NtfyMessage,
+    state::StateStore, Args, Candidate, Detection, DetectionEvent, Kind, Notifier,
+};
+
+/// The detections of one repo, held back for the pass's digest.
+pub struct DigestEntry {
+    pub repo: String,
+    pub notifiers: Vec<Notifier>,
+    /// What to announce, oldest first
+    pub candidates: Vec<Candidate>,
+    /// Tag and kind `last_seen_tag` moves to once delivered (may be a
+    /// filtered tag newer than the announced ones)
+    pub newest: (String, Kind),
+    pub etag: Option<(Kind, String)>,
+}
+
+/// Sends the detections collected over a pass: every chat target gets one
+/// message listing all of its repos, webhooks and --stdout still get one per
+/// detection. A repo's state only moves on once at least one of its targets
+/// got the digest, otherwise its tags are detected again next pass.
+pub async fn send(
+    mut entries: Vec<DigestEntry>,
+    client: &reqwest::Client,
+    state: &Mutex<Box<dyn StateStore>>,
+    args: &Args,
+) {
+    if entries.is_empty() {
+        return;
+    }
+    entries.sort_by(|a, b| a.repo.cmp(&b.repo));
+
+    let mut targets: Vec<&Notifier> = Vec::new();
+    for notifier in entries.iter().flat_map(|e| &e.notifiers) {
+        if !targets.iter().any(|t| t.to_string() == notifier.to_string()) {
+            targets.push(notifier);
+        }
+    }
+    let mut delivered = HashSet::new();
+    for notifier in targets {
+        let target = notifier.to_string();
+        let items: Vec<(&str, &Candidate)> = entries
+            .iter()
+            .filter(|e| e.notifiers.iter().any(|n| n.to_string() == target))
+            .flat_map(|e| e.candidates.iter().map(move |c| (e.repo.as_str(), c)))
+            .collect();
+        let res = match notifier {
+            Notifier::Webhook { .. } | Notifier::Stdout => {
+                let mut res = Ok(());
+                for (repo, candidate) in &items {
+                    let detection = Detection::new(repo, candidate, args);
+                    let text = notifier.format_message(&detection, args);
+                    res = res.and(notifier.send(client, text, &[], args.dry_run).await);
+                }
+                res
+            }
+            _ => {
+                let text = digest_message(&items, args, notifier);
+                notifier.send(client, text, &[], args.dry_run).await
+            }
+        };
+        match res {
+            Ok(()) => {
+                METRICS.notifications_sent.with_label_values(&[notifier.kind()]).inc();
+                delivered.insert(target);
+            }
+            Err(e) => error!(%target, error=?e, "sending the digest failed"),
+        }
+    }
+    info!(repos = entries.len(), targets = delivered.len(), "digest delivered");
+
+    for entry in &entries {
+        let repo = entry.repo.as_str();
+        let sent: Vec<String> = entry
+            .notifiers
+            .iter()
+            .map(Notifier::to_string)
+            .filter(|n| delivered.contains(n))
+            .collect();
+        if let Some(path) = &args.event_log {
+            for candidate in &entry.candidates {
+                let event = DetectionEvent {
+                    timestamp: Utc::now(),
+                    repo,
+                    tag: &candidate.tag,
+                    prev_tag: candidate.previous.as_deref(),
+                    kind: candidate.kind,
+                    notified_targets: &sent,
+                };
+                if let Err(e) = append_event(path, &event) {
+                    warn!(%repo, path = %path.display(), error=?e, "writing the event log failed");
+                }
+            }
+        }
+        if sent.is_empty() {
+            warn!(%repo, "digest reached none of the repo's targets, retrying next pass");
+            continue;
+        }
+        if let Err(e) = advance(entry, state) {
+            error!(%repo, error=?e, "saving state after the digest failed");
+        }
+    }
+}
+
+fn advance(entry: &DigestEntry, state: &Mutex<Box<dyn StateStore>>) -> anyhow::Result<()> {
+    let mut store = state.lock().expect("state lock poisoned");
+    let (tag, kind) = &entry.newest;
+    store.update(&entry.repo, &mut |r| {
+        r.last_seen_tag = Some(tag.clone());
+        r.kind = Some(*kind);
+        r.last_notified_at = Some(Utc::now());
+    })?;
+    if let Some((kind, etag)) = &entry.etag {
+        store.set_etag(&entry.repo, *kind, etag)?;
+    }
+    Ok(())
+}
+
+/// One message for `notifier` with a `repo: tag url` line per detection
+/// under an "N new tags" header (the email subject, the Teams or ntfy title).
+fn digest_message(items: &[(&str, &Candidate)], args: &Args, notifier: &Notifier) -> String {
+    let escape = escape_for(notifier);
+    let icon = if args.no_emoji { "" } else { "\u{1F680} " };
+    let title = match items.len() {
+        1 => "1 new tag".to_string(),
+        n => format!("{n} new tags"),
+    };
+    let lines: Vec<String> = items
+        .iter()
+        .map(|(repo, candidate)| {
+            let tag = &candidate.tag;
+            let label = match candidate.kind {
+                Kind::Commit => &tag[..tag.len().min(7)],
+                _ => tag.as_str(),
+            };
+            let url = candidate_url(repo, candidate, args);
+            format!("• {}: {} {}", escape(repo), escape(label), escape(&url))
+        })
+        .collect();
+    match notifier {
+        Notifier::Teams { .. } => serde_json::json!({
+            "@type": "MessageCard",
+            "@context": "https://schema.org/extensions",
+            "summary": title,
+            "title": title,
+            "text": lines.join("\n\n"),
+        })
+        .to_string(),
+        _ if notifier.takes_ntfy_message() => {
+            let message = NtfyMessage {
+                title,
+                message: lines.join("\n"),
+                click: items
+                    .first()
+                    .map(|(repo, c)| candidate_url(repo, c, args))
+                    .unwrap_or_default(),
+            };
+            serde_json::to_string(&message).expect("ntfy message serializes")
+        }
+        _ => format!("{icon}{}\n{}", escape(&title), lines.join("\n")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{discord, test_args};
+
+    #[test]
+    fn digest_message_lists_every_detection() {
+        let args = test_args(&["--no-emoji"]);
+        let candidate = |tag: &str| Candidate::new(tag, Kind::Tag);
+        let (a, b) = (candidate("v1.0.0"), candidate("v2.0.0"));
+        let discord = discord();
+        assert_eq!(
+            digest_message(&[("o/a", &a), ("o/b", &b)], &args, &discord),
+            "2 new tags\n\
+             • o/a: v1.0.0 https://github.com/o/a/releases/tag/v1.0.0\n\
+             • o/b: v2.0.0 https://github.com/o/b/releases/tag/v2.0.0"
+        );
+    }
+}
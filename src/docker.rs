@@ -0,0 +1,203 @@
+use anyhow::{Context, Result};
+use reqwest::{header, StatusCode};
+use serde::Deserialize;
+use tracing::warn;
+
+/// Entries starting with this are container images, checked through the
+/// registry's v2 API instead of GitHub.
+const PREFIX: &str = "docker://";
+
+/// Where the v2 API of Docker Hub lives (`docker.io` itself doesn't serve it).
+pub const DOCKER_HUB: &str = "registry-1.docker.io";
+
+/// Tags asked for per page, and the most pages followed for one image.
+const PAGE_SIZE: usize = 1000;
+const MAX_PAGES: usize = 20;
+
+/// The image of a `docker://<image>` entry.
+pub fn image(entry: &str) -> Option<&str> {
+    entry.strip_prefix(PREFIX)
+}
+
+/// The registry host and repository name of `image`: a first component
+/// with a `.` or `:` (or `localhost`) names the registry, like `docker pull`
+/// reads it, else it is on `default_registry`. Docker Hub's official images
+/// live under `library/`.
+pub fn locate<'a>(image: &'a str, default_registry: &'a str) -> (&'a str, String) {
+    let (registry, name) = match image.split_once('/') {
+        Some((host, name)) if host.contains(['.', ':']) || host == "localhost" => (host, name),
+        _ => (default_registry, image),
+    };
+    let registry = match registry {
+        "docker.io" | "index.docker.io" => DOCKER_HUB,
+        other => other,
+    };
+    if registry == DOCKER_HUB && !name.contains('/') {
+        (registry, format!("library/{name}"))
+    } else {
+        (registry, name.to_string())
+    }
+}
+
+/// A page to link to for the image: its Docker Hub page, else the
+/// registry's URL for it (which e.g. ghcr.io redirects to the package).
+pub fn web_url(registry: &str, name: &str) -> String {
+    if registry != DOCKER_HUB {
+        return format!("https://{registry}/{name}");
+    }
+    match name.strip_prefix("library/") {
+        Some(official) => format!("https://hub.docker.com/_/{official}"),
+        None => format!("https://hub.docker.com/r/{name}"),
+    }
+}
+
+#[derive(Deserialize)]
+struct TagList {
+    /// `null` for an image without tags
+    tags: Option<Vec<String>>,
+}
+
+#[derive(Deserialize)]
+struct Token {
+    token: Option<String>,
+    access_token: Option<String>,
+}
+
+/// Lists every tag of `name` on `registry`, following the registry's
+/// pagination. Anonymous access works for public images; a registry asking
+/// for a bearer token gets one from the realm its 401 names, with `auth`
+/// (user, password) as basic credentials if given.
+pub async fn tags(
+    client: &reqwest::Client,
+    registry: &str,
+    name: &str,
+    auth: Option<(&str, &str)>,
+) -> Result<Vec<String>> {
+    let origin = if registry.starts_with("localhost") || registry.starts_with("127.0.0.1") {
+        format!("http://{registry}")
+    } else {
+        format!("https://{registry}")
+    };
+    let mut url = format!("{origin}/v2/{name}/tags/list?n={PAGE_SIZE}");
+    let mut token = None;
+    let mut tags = Vec::new();
+    let mut pages = 0;
+    while pages < MAX_PAGES {
+        let mut request = client.get(&url);
+        request = match (&token, auth) {
+            (Some(token), _) => request.bearer_auth(token),
+            (None, Some((user, pass))) => request.basic_auth(user, Some(pass)),
+            (None, None) => request,
+        };
+        let resp = request.send().await.with_context(|| format!("GET {url}"))?;
+        let status = resp.status();
+        if status == StatusCode::UNAUTHORIZED && token.is_none() {
+            let challenge = resp
+                .headers()
+                .get(header::WWW_AUTHENTICATE)
+                .and_then(|v| v.to_str().ok())
+                .and_then(bearer_challenge)
+                .with_context(|| format!("{url}: 401 without a bearer challenge"))?;
+            token = Some(fetch_token(client, &challenge, auth).await?);
+            continue;
+        }
+        if !status.is_success() {
+            let body = resp.text().await.unwrap_or_default();
+            anyhow::bail!("{url}: {status} {}", body.trim());
+        }
+        let next = resp
+            .headers()
+            .get(header::LINK)
+            .and_then(|v| v.to_str().ok())
+            .and_then(next_link)
+            .map(str::to_string);
+        let page: TagList = resp.json().await.with_context(|| format!("parsing {url}"))?;
+        tags.extend(page.tags.unwrap_or_default());
+        match next {
+            Some(next) if next.starts_with('/') => url = format!("{origin}{next}"),
+            Some(next) => url = next,
+            None => return Ok(tags),
+        }
+        pages += 1;
+    }
+    warn!(registry, image = name, pages, "image has more tags than are fetched, stopping");
+    Ok(tags)
+}
+
+/// Asks the challenge's realm for a pull token.
+async fn fetch_token(
+    client: &reqwest::Client,
+    challenge: &[(String, String)],
+    auth: Option<(&str, &str)>,
+) -> Result<String> {
+    let realm = challenge
+        .iter()
+        .find(|(key, _)| key == "realm")
+        .map(|(_, realm)| realm.as_str())
+        .context("bearer challenge without a realm")?;
+    let params: Vec<_> = challenge.iter().filter(|(key, _)| key != "realm").collect();
+    let mut request = client.get(realm).query(&params);
+    if let Some((user, pass)) = auth {
+        request = request.basic_auth(user, Some(pass));
+    }
+    let resp = request.send().await.with_context(|| format!("GET {realm}"))?;
+    let status = resp.status();
+    if !status.is_success() {
+        anyhow::bail!("registry token from {realm}: {status}");
+    }
+    let token: Token = resp.json().await.context("parsing registry token")?;
+    token.token.or(token.access_token).context("registry token response without a token")
+}
+
+/// The parameters of a `Bearer realm="...",service="...",scope="..."`
+/// WWW-Authenticate header.
+fn bearer_challenge(header: &str) -> Option<Vec<(String, String)>> {
+    let params = header.strip_prefix("Bearer ")?;
+    let mut out = Vec::new();
+    let mut rest = params.trim();
+    while !rest.is_empty() {
+        let (key, after) = rest.split_once('=')?;
+        let after = after.strip_prefix('"')?;
+        let (value, after) = after.split_once('"')?;
+        out.push((key.trim().to_string(), value.to_string()));
+        rest = after.trim_start_matches(',').trim();
+    }
+    Some(out)
+}
+
+/// The target of a `<...>; rel="next"` Link header.
+fn next_link(header: &str) -> Option<&str> {
+    header.split(',').find_map(|link| {
+        let (target, params) = link.split_once(';')?;
+        params
+            .contains("rel=\"next\"")
+            .then(|| target.trim().trim_start_matches('<').trim_end_matches('>'))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locate_reads_registry_and_official_images() {
+        assert_eq!(locate("nginx", DOCKER_HUB), (DOCKER_HUB, "library/nginx".into()));
+        assert_eq!(locate("grafana/grafana", DOCKER_HUB), (DOCKER_HUB, "grafana/grafana".into()));
+        assert_eq!(locate("ghcr.io/o/img", DOCKER_HUB), ("ghcr.io", "o/img".into()));
+        assert_eq!(locate("docker.io/redis", "ghcr.io"), (DOCKER_HUB, "library/redis".into()));
+        assert_eq!(web_url(DOCKER_HUB, "library/nginx"), "https://hub.docker.com/_/nginx");
+        assert_eq!(web_url("ghcr.io", "o/img"), "https://ghcr.io/o/img");
+    }
+
+    #[test]
+    fn parses_challenge_and_next_link() {
+        let header = r#"Bearer realm="https://auth.docker.io/token",service="registry.docker.io",scope="repository:library/nginx:pull""#;
+        let challenge = bearer_challenge(header).unwrap();
+        assert_eq!(challenge[0], ("realm".into(), "https://auth.docker.io/token".into()));
+        assert_eq!(challenge[2], ("scope".into(), "repository:library/nginx:pull".into()));
+        assert_eq!(bearer_challenge(r#"Basic realm="x""#), None);
+        let link = r#"</v2/library/nginx/tags/list?last=1.25&n=1000>; rel="next""#;
+        assert_eq!(next_link(link), Some("/v2/library/nginx/tags/list?last=1.25&n=1000"));
+        assert_eq!(next_link(""), None);
+    }
+}
@@ -0,0 +1,70 @@
+use anyhow::{Context, Result};
+use std::time::Duration;
+use tokio::process::Command;
+
+/// Entries starting with this are plain git remotes, checked with
+/// `git ls-remote` instead of the GitHub API.
+const PREFIX: &str = "git+";
+
+/// The remote URL of a `git+<url>` entry.
+pub fn remote_url(entry: &str) -> Option<&str> {
+    entry.strip_prefix(PREFIX)
+}
+
+/// A page to link to for `url`: the repo's web page for http(s) remotes
+/// (most forges serve it without the `.git`), else the URL itself.
+pub fn web_url(url: &str) -> &str {
+    if url.starts_with("https://") || url.starts_with("http://") {
+        url.strip_suffix(".git").unwrap_or(url)
+    } else {
+        url
+    }
+}
+
+/// Lists the tags of the remote at `url` as `(name, object id)`, highest
+/// version first as git sorts them. Runs the `git` binary from `PATH`, which
+/// must not prompt for credentials.
+pub async fn remote_tags(url: &str, timeout: Duration) -> Result<Vec<(String, String)>> {
+    let output = Command::new("git")
+        .args(["ls-remote", "--tags", "--refs", "--sort=-v:refname", url])
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .kill_on_drop(true)
+        .output();
+    let output = tokio::time::timeout(timeout, output)
+        .await
+        .with_context(|| format!("git ls-remote {url} timed out"))?
+        .context("running git (is it installed?)")?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "git ls-remote {url} failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(parse_ls_remote(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Parses `<oid>\trefs/tags/<name>` lines, skipping anything else.
+fn parse_ls_remote(out: &str) -> Vec<(String, String)> {
+    out.lines()
+        .filter_map(|line| {
+            let (oid, name) = line.split_once('\t')?;
+            let tag = name.strip_prefix("refs/tags/")?;
+            Some((tag.to_string(), oid.to_string()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_ls_remote_reads_tag_refs() {
+        let out = "1111\trefs/tags/v1.10.0\n2222\trefs/tags/v1.9.0\n3333\trefs/heads/main\n";
+        assert_eq!(
+            parse_ls_remote(out),
+            [("v1.10.0".to_string(), "1111".to_string()), ("v1.9.0".into(), "2222".into())]
+        );
+    }
+}
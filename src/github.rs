@@ -0,0 +1,701 @@
+use chrono::{DateTime, Duration, Utc};
+use http::{
+    header::{HeaderMap, HeaderValue, ETAG, IF_NONE_MATCH},
+    StatusCode,
+};
+use octocrab::{
+    auth::AppAuth,
+    service::middleware::{base_uri::BaseUriLayer, extra_headers::ExtraHeadersLayer},
+    AuthState,
+};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, OnceLock},
+    task::{Context, Poll},
+};
+use tracing::{debug, warn};
+
+use crate::metrics::METRICS;
+
+/// Fallback pause when GitHub throttles us without saying for how long.
+const DEFAULT_RATE_LIMIT_PAUSE_SECS: i64 = 60;
+
+/// Largest `per_page` GitHub accepts on listing endpoints.
+const LIST_PAGE_SIZE: usize = 100;
+
+/// What [`encode`] leaves alone: RFC 3986's unreserved characters.
+const UNRESERVED: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'.').remove(b'_').remove(b'~');
+
+/// `s` percent-encoded for use as one path segment or query value.
+pub fn encode(s: &str) -> String {
+    utf8_percent_encode(s, UNRESERVED).to_string()
+}
+
+/// `tag` as the tail of a route, each `/`-separated segment encoded, so
+/// tags like `v1.0+build` or `a#b` reach GitHub as they're named.
+fn tag_path(tag: &str) -> String {
+    tag.split('/').map(encode).collect::<Vec<_>>().join("/")
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum GithubError {
+    #[error("GitHub rate limit hit, resets at {reset_at}")]
+    RateLimited { reset_at: DateTime<Utc> },
+    #[error("GitHub returned {status}: {body}")]
+    Status { status: StatusCode, body: String },
+    #[error(transparent)]
+    Octocrab(#[from] octocrab::Error),
+    #[error("parsing GitHub response: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("GitHub GraphQL query failed: {0}")]
+    Graphql(String),
+}
+
+/// Outcome of a conditional GET.
+#[derive(Debug)]
+pub enum Fetched<T> {
+    /// The server answered 304 for the `If-None-Match` ETag we sent.
+    NotModified,
+    Modified { value: T, etag: Option<String> },
+}
+
+impl<T> Fetched<T> {
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> Fetched<U> {
+        match self {
+            Fetched::NotModified => Fetched::NotModified,
+            Fetched::Modified { value, etag } => Fetched::Modified {
+                value: f(value),
+                etag,
+            },
+        }
+    }
+}
+
+/// GETs `route` (relative to the API base) and deserializes the JSON body.
+///
+/// Unlike octocrab's typed builders this keeps the response headers around,
+/// so rate limiting surfaces as [`GithubError::RateLimited`] with the reset time.
+/// When `etag` is given it is sent as `If-None-Match`; a 304 reply doesn't
+/// count against the rate limit.
+pub async fn get_json<T: DeserializeOwned>(
+    octo: &octocrab::Octocrab,
+    route: &str,
+    etag: Option<&str>,
+) -> Result<Fetched<T>, GithubError> {
+    let res = fetch_json(octo, route, etag).await;
+    if res.is_err() {
+        METRICS.github_api_errors.inc();
+    }
+    res
+}
+
+async fn fetch_json<T: DeserializeOwned>(
+    octo: &octocrab::Octocrab,
+    route: &str,
+    etag: Option<&str>,
+) -> Result<Fetched<T>, GithubError> {
+    let mut headers = HeaderMap::new();
+    if let Some(value) = etag.and_then(|e| HeaderValue::from_str(e).ok()) {
+        headers.insert(IF_NONE_MATCH, value);
+    }
+    let resp = octo._get_with_headers(route, Some(headers)).await?;
+    let status = resp.status();
+    if status == StatusCode::NOT_MODIFIED {
+        return Ok(Fetched::NotModified);
+    }
+    if let Some(reset_at) = rate_limit_reset(status, resp.headers(), Utc::now()) {
+        return Err(GithubError::RateLimited { reset_at });
+    }
+    let etag = resp
+        .headers()
+        .get(ETAG)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+    let body = octo.body_to_string(resp).await?;
+    if !status.is_success() {
+        return Err(GithubError::Status { status, body });
+    }
+    Ok(Fetched::Modified {
+        value: serde_json::from_str(&body)?,
+        etag,
+    })
+}
+
+/// Set by [`cache_responses`].
+static CACHE_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Keeps the body and ETag of every GitHub response requested without an
+/// ETag (repo lookups, owner listings) as a file in `dir`, so a later
+/// process can ask with `If-None-Match` and reuse the body on a 304.
+/// Entries are per credentials, so one token never gets the answer GitHub
+/// gave another (a private repo, a longer owner listing).
+pub fn cache_responses(dir: &Path) -> std::io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let _ = CACHE_DIR.set(dir.to_path_buf());
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedResponse {
+    etag: String,
+    body: String,
+}
+
+/// `<dir>/<sha256 of identity and url>.json`: urls contain `/` and `?`, and
+/// the identity is a token.
+fn cache_path(dir: &Path, identity: &str, url: &str) -> PathBuf {
+    let key = format!("{identity}\n{url}");
+    let digest = ring::digest::digest(&ring::digest::SHA256, key.as_bytes());
+    let name: String = digest.as_ref().iter().map(|b| format!("{b:02x}")).collect();
+    dir.join(format!("{name}.json"))
+}
+
+fn read_cached(dir: &Path, identity: &str, url: &str) -> Option<CachedResponse> {
+    let json = fs::read_to_string(cache_path(dir, identity, url)).ok()?;
+    serde_json::from_str(&json).ok()
+}
+
+/// Writes via a temporary file, so a concurrent run never reads half an entry.
+fn write_cached(
+    dir: &Path,
+    identity: &str,
+    url: &str,
+    cached: &CachedResponse,
+) -> std::io::Result<()> {
+    let path = cache_path(dir, identity, url);
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, serde_json::to_vec(cached)?)?;
+    fs::rename(tmp, path)
+}
+
+/// How a client built by [`octocrab_over`] authenticates.
+pub enum Auth {
+    None,
+    Token(String),
+    /// The app and the installation whose token it uses
+    App(AppAuth, u64),
+}
+
+/// An octocrab client whose requests are sent by `client` instead of
+/// octocrab's own hyper client, which knows nothing about proxies.
+/// GETs that fail to connect or get a 5xx are retried up to `max_retries`
+/// times, and go through the [`cache_responses`] cache of `auth`.
+pub fn octocrab_over(
+    client: reqwest::Client,
+    base_url: Option<&str>,
+    auth: Auth,
+    max_retries: u32,
+) -> anyhow::Result<octocrab::Octocrab> {
+    let base_uri = base_url.unwrap_or("https://api.github.com").parse()?;
+    let mut headers = vec![(
+        http::header::USER_AGENT,
+        HeaderValue::from_static("dockmasterbot"),
+    )];
+    let (state, identity) = match auth {
+        Auth::None => (AuthState::None, String::new()),
+        Auth::Token(token) => {
+            headers.push((http::header::AUTHORIZATION, format!("Bearer {token}").parse()?));
+            (AuthState::None, format!("token {token}"))
+        }
+        Auth::App(app, installation) => {
+            let identity = format!("app {} installation {installation}", app.app_id.0);
+            (AuthState::App(app), identity)
+        }
+    };
+    let service = ReqwestService {
+        client,
+        max_retries,
+        identity: identity.into(),
+    };
+    Ok(octocrab::OctocrabBuilder::new_empty()
+        .with_service(service)
+        .with_layer(&BaseUriLayer::new(base_uri))
+        .with_layer(&ExtraHeadersLayer::new(Arc::new(headers)))
+        .with_auth(state)
+        .build()?)
+}
+
+/// Sends octocrab's requests with a `reqwest::Client`, retrying GETs on
+/// connection errors and 5xx with exponential backoff. Other requests and
+/// 4xx responses (a 404 or 422 won't go away) are returned as they are.
+#[derive(Clone)]
+struct ReqwestService {
+    client: reqwest::Client,
+    max_retries: u32,
+    /// Who the requests are made as, keying the response cache
+    identity: Arc<str>,
+}
+
+impl tower::Service<http::Request<String>> for ReqwestService {
+    type Response = http::Response<hyper::Body>;
+    type Error = reqwest::Error;
+    type Future = futures::future::BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: http::Request<String>) -> Self::Future {
+        let ReqwestService {
+            client,
+            max_retries,
+            identity,
+        } = self.clone();
+        Box::pin(async move {
+            // octocrab and reqwest are on different major versions of `http`,
+            // so everything is copied over by name and bytes
+            let (parts, body) = req.into_parts();
+            let method = reqwest::Method::from_bytes(parts.method.as_str().as_bytes())
+                .expect("octocrab sends standard methods");
+            let get = method == reqwest::Method::GET;
+            let max_retries = if get { max_retries } else { 0 };
+            // GETs whose caller doesn't keep an ETag itself are sent with the
+            // cached one, and answered with the cached body while it matches
+            let url = parts.uri.to_string();
+            let conditional = parts.headers.contains_key(IF_NONE_MATCH);
+            let cache = CACHE_DIR.get().filter(|_| get && !conditional);
+            let cached = cache.and_then(|dir| read_cached(dir, &identity, &url));
+            let mut attempt = 0;
+            let resp = loop {
+                let mut request = client.request(method.clone(), &url).body(body.clone());
+                for (name, value) in &parts.headers {
+                    request = request.header(name.as_str(), value.as_bytes());
+                }
+                if let Some(cached) = &cached {
+                    request = request.header(IF_NONE_MATCH.as_str(), cached.etag.as_str());
+                }
+                let res = request.send().await;
+                let failure = match &res {
+                    Ok(resp) => resp.status().is_server_error().then(|| resp.status().to_string()),
+                    Err(e) => (!e.is_builder()).then(|| e.to_string()),
+                };
+                match failure {
+                    Some(failure) if attempt < max_retries => {
+                        let wait = std::time::Duration::from_secs(1 << attempt.min(6));
+                        attempt += 1;
+                        warn!(
+                            uri = %parts.uri, attempt, ?wait, error = %failure,
+                            "GitHub request failed, retrying"
+                        );
+                        tokio::time::sleep(wait).await;
+                    }
+                    // the last answer goes to the caller, 5xx or not
+                    _ => break res?,
+                }
+            };
+            record_rate_limit(resp.headers());
+            let not_modified = resp.status() == reqwest::StatusCode::NOT_MODIFIED;
+            if let Some(cached) = cached.filter(|_| not_modified) {
+                return Ok(http::Response::builder()
+                    .status(StatusCode::OK)
+                    .header(ETAG, cached.etag)
+                    .body(hyper::Body::from(cached.body))
+                    .expect("a cached ETag is a valid header"));
+            }
+            let mut response = http::Response::builder().status(resp.status().as_u16());
+            for (name, value) in resp.headers() {
+                response = response.header(name.as_str(), value.as_bytes());
+            }
+            let etag = resp.headers().get(ETAG.as_str()).and_then(|v| v.to_str().ok());
+            let etag = etag.filter(|_| resp.status().is_success()).map(str::to_string);
+            let body = resp.bytes().await?;
+            if let (Some(dir), Some(etag)) = (cache, etag) {
+                let cached = CachedResponse {
+                    etag,
+                    body: String::from_utf8_lossy(&body).into_owned(),
+                };
+                if let Err(e) = write_cached(dir, &identity, &url, &cached) {
+                    warn!(%url, error=?e, "caching the GitHub response failed");
+                }
+            }
+            Ok(response
+                .body(hyper::Body::from(body))
+                .expect("status and headers came from a valid response"))
+        })
+    }
+}
+
+/// Logs (at debug) and records as gauges the `x-ratelimit-*` headers GitHub
+/// sends with every API response, so the poll interval can be tuned before
+/// requests get throttled.
+fn record_rate_limit(headers: &reqwest::header::HeaderMap) {
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+    let number = |name: &str| header(name).and_then(|v| v.parse::<i64>().ok());
+    let (Some(remaining), Some(limit)) =
+        (number("x-ratelimit-remaining"), number("x-ratelimit-limit"))
+    else {
+        return;
+    };
+    let resource = header("x-ratelimit-resource").unwrap_or("core");
+    debug!(resource, remaining, limit, "GitHub rate limit");
+    METRICS.github_rate_limit_remaining.with_label_values(&[resource]).set(remaining);
+    METRICS.github_rate_limit_limit.with_label_values(&[resource]).set(limit);
+}
+
+/// The parts of a repository listing entry the watcher uses.
+#[derive(Debug, Deserialize)]
+pub struct RepoSummary {
+    pub full_name: String,
+    #[serde(default)]
+    pub archived: bool,
+    #[serde(default)]
+    pub disabled: bool,
+}
+
+#[derive(Deserialize)]
+struct User {
+    login: String,
+}
+
+/// The login the token `octo` was built with belongs to.
+pub async fn token_login(octo: &octocrab::Octocrab) -> Result<String, GithubError> {
+    match get_json::<User>(octo, "/user", None).await? {
+        Fetched::Modified { value, .. } => Ok(value.login),
+        Fetched::NotModified => unreachable!("/user is fetched without an ETag"),
+    }
+}
+
+#[derive(Deserialize)]
+struct GitRef {
+    object: GitObject,
+}
+
+#[derive(Deserialize)]
+struct GitObject {
+    #[serde(rename = "type")]
+    kind: String,
+    sha: String,
+}
+
+#[derive(Deserialize)]
+struct TagObject {
+    verification: Verification,
+}
+
+#[derive(Deserialize)]
+struct Verification {
+    verified: bool,
+}
+
+/// Whether `tag` in `repo` (`owner/name`) is an annotated tag whose signature
+/// GitHub verified. Lightweight tags have no tag object and so no signature.
+pub async fn tag_verified(
+    octo: &octocrab::Octocrab,
+    repo: &str,
+    tag: &str,
+) -> Result<bool, GithubError> {
+    let route = format!("/repos/{repo}/git/ref/tags/{}", tag_path(tag));
+    // without an ETag GitHub never answers 304
+    let Fetched::Modified { value: git_ref, .. } = get_json::<GitRef>(octo, &route, None).await?
+    else {
+        return Ok(false);
+    };
+    if git_ref.object.kind != "tag" {
+        return Ok(false);
+    }
+    let route = format!("/repos/{repo}/git/tags/{}", git_ref.object.sha);
+    match get_json::<TagObject>(octo, &route, None).await? {
+        Fetched::Modified { value, .. } => Ok(value.verification.verified),
+        Fetched::NotModified => Ok(false),
+    }
+}
+
+/// The current `owner/repo` name of `full_name`, which differs from it once
+/// the repository has been renamed or transferred (GitHub redirects the old
+/// name, and octocrab follows the redirect).
+pub async fn current_name(
+    octo: &octocrab::Octocrab,
+    full_name: &str,
+) -> Result<String, GithubError> {
+    match get_json::<RepoSummary>(octo, &format!("/repos/{full_name}"), None).await? {
+        Fetched::Modified { value, .. } => Ok(value.full_name),
+        // without an ETag GitHub never answers 304
+        Fetched::NotModified => Ok(full_name.to_string()),
+    }
+}
+
+/// Whether `tag` still exists in `repo` (`owner/name`): its release when
+/// `release` is set, else the tag ref itself.
+pub async fn tag_exists(
+    octo: &octocrab::Octocrab,
+    repo: &str,
+    tag: &str,
+    release: bool,
+) -> Result<bool, GithubError> {
+    let route = if release {
+        format!("/repos/{repo}/releases/tags/{}", tag_path(tag))
+    } else {
+        format!("/repos/{repo}/git/ref/tags/{}", tag_path(tag))
+    };
+    match get_json::<serde_json::Value>(octo, &route, None).await {
+        Ok(_) => Ok(true),
+        Err(GithubError::Status {
+            status: StatusCode::NOT_FOUND,
+            ..
+        }) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Lists every repository of `owner`, trying it as an org first and
+/// falling back to the user endpoint when there is no such org.
+pub async fn list_owner_repos(
+    octo: &octocrab::Octocrab,
+    owner: &str,
+) -> Result<Vec<RepoSummary>, GithubError> {
+    match list_all_pages(octo, &format!("/orgs/{owner}/repos")).await {
+        Err(GithubError::Status {
+            status: StatusCode::NOT_FOUND,
+            ..
+        }) => list_all_pages(octo, &format!("/users/{owner}/repos")).await,
+        res => res,
+    }
+}
+
+async fn list_all_pages<T: DeserializeOwned>(
+    octo: &octocrab::Octocrab,
+    route: &str,
+) -> Result<Vec<T>, GithubError> {
+    let mut items = Vec::new();
+    for page in 1.. {
+        let route = format!("{route}?per_page={LIST_PAGE_SIZE}&page={page}");
+        // without an ETag GitHub never answers 304
+        let Fetched::Modified { value, .. } = get_json::<Vec<T>>(octo, &route, None).await? else {
+            break;
+        };
+        let last = value.len() < LIST_PAGE_SIZE;
+        items.extend(value);
+        if last {
+            break;
+        }
+    }
+    Ok(items)
+}
+
+/// Repos asked about per GraphQL query, well within GitHub's node limits.
+pub const GRAPHQL_BATCH: usize = 50;
+
+/// Tags asked about per repo, enough to tell whether their commit order
+/// agrees with their semver order.
+pub const GRAPHQL_TAGS: usize = 30;
+
+/// What GraphQL reports as the newest of a repo's releases and tags.
+#[derive(Debug, Default, PartialEq)]
+pub struct NewestTags {
+    /// Tag of the release marked "Latest"
+    pub latest_release: Option<String>,
+    /// Tag of the most recently created release
+    pub newest_release: Option<String>,
+    /// Up to [`GRAPHQL_TAGS`] tags, the one on the most recent commit first
+    pub tags: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct GraphqlResponse {
+    /// Keyed by the `r<index>` alias of each repo; `null` for missing ones
+    data: Option<std::collections::HashMap<String, Option<GraphqlRepo>>>,
+    #[serde(default)]
+    errors: Vec<GraphqlError>,
+}
+
+#[derive(Deserialize)]
+struct GraphqlError {
+    message: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GraphqlRepo {
+    latest_release: Option<ReleaseNode>,
+    releases: Nodes<ReleaseNode>,
+    refs: Option<Nodes<RefNode>>,
+}
+
+#[derive(Deserialize)]
+struct Nodes<T> {
+    nodes: Vec<T>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ReleaseNode {
+    tag_name: String,
+}
+
+#[derive(Deserialize)]
+struct RefNode {
+    name: String,
+}
+
+/// Asks for the newest release and tag of each `owner/name` in `repos` in
+/// one GraphQL query (up to [`GRAPHQL_BATCH`] repos). Repos GitHub couldn't
+/// resolve are left out of the result; a failed query is an error.
+pub async fn newest_tags<'a>(
+    octo: &octocrab::Octocrab,
+    repos: &[&'a str],
+) -> Result<Vec<(&'a str, NewestTags)>, GithubError> {
+    let query = newest_tags_query(repos);
+    let res = octo.graphql::<GraphqlResponse>(&serde_json::json!({ "query": query })).await;
+    if res.is_err() {
+        METRICS.github_api_errors.inc();
+    }
+    let res = res?;
+    for e in &res.errors {
+        debug!(error = %e.message, "GraphQL batch reported an error");
+    }
+    let Some(mut data) = res.data else {
+        METRICS.github_api_errors.inc();
+        let messages: Vec<_> = res.errors.into_iter().map(|e| e.message).collect();
+        return Err(GithubError::Graphql(messages.join("; ")));
+    };
+    Ok(repos
+        .iter()
+        .enumerate()
+        .filter_map(|(i, repo)| {
+            let found = data.remove(&format!("r{i}")).flatten()?;
+            let tags = found.refs.map(|r| r.nodes.into_iter().map(|r| r.name).collect());
+            let newest = NewestTags {
+                latest_release: found.latest_release.map(|r| r.tag_name),
+                newest_release: found.releases.nodes.into_iter().next().map(|r| r.tag_name),
+                tags: tags.unwrap_or_default(),
+            };
+            Some((*repo, newest))
+        })
+        .collect())
+}
+
+fn newest_tags_query(repos: &[&str]) -> String {
+    let fields = format!(
+        "latestRelease {{ tagName }} \
+         releases(first: 1, orderBy: {{field: CREATED_AT, direction: DESC}}) \
+         {{ nodes {{ tagName }} }} \
+         refs(refPrefix: \"refs/tags/\", first: {GRAPHQL_TAGS}, \
+         orderBy: {{field: TAG_COMMIT_DATE, direction: DESC}}) {{ nodes {{ name }} }}"
+    );
+    let aliases: Vec<String> = repos
+        .iter()
+        .enumerate()
+        .map(|(i, repo)| {
+            let (owner, name) = repo.split_once('/').unwrap_or((repo, ""));
+            // JSON string literals are valid GraphQL ones
+            let (owner, name) = (serde_json::json!(owner), serde_json::json!(name));
+            format!("r{i}: repository(owner: {owner}, name: {name}) {{ {fields} }}")
+        })
+        .collect();
+    format!("query {{ {} }}", aliases.join(" "))
+}
+
+/// Works out when a throttled request may be retried, or `None` if the
+/// response isn't a rate-limit rejection (a 403 can also mean "no access").
+fn rate_limit_reset(
+    status: StatusCode,
+    headers: &HeaderMap,
+    now: DateTime<Utc>,
+) -> Option<DateTime<Utc>> {
+    if status != StatusCode::FORBIDDEN && status != StatusCode::TOO_MANY_REQUESTS {
+        return None;
+    }
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+
+    if let Some(secs) = header("retry-after").and_then(|v| v.parse::<i64>().ok()) {
+        return Some(now + Duration::seconds(secs));
+    }
+    if header("x-ratelimit-remaining") == Some("0") {
+        let reset = header("x-ratelimit-reset")
+            .and_then(|v| v.parse::<i64>().ok())
+            .and_then(|ts| DateTime::from_timestamp(ts, 0));
+        return Some(reset.unwrap_or(now + Duration::seconds(DEFAULT_RATE_LIMIT_PAUSE_SECS)));
+    }
+    if status == StatusCode::TOO_MANY_REQUESTS {
+        return Some(now + Duration::seconds(DEFAULT_RATE_LIMIT_PAUSE_SECS));
+    }
+    None
+}
+
+/// The reset time if `e` is (or wraps) a [`GithubError::RateLimited`].
+pub fn rate_limited_until(e: &anyhow::Error) -> Option<DateTime<Utc>> {
+    match e.downcast_ref::<GithubError>() {
+        Some(GithubError::RateLimited { reset_at }) => Some(*reset_at),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cached_responses_round_trip_per_identity_and_url() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        let cached = CachedResponse {
+            etag: "\"abc\"".into(),
+            body: r#"{"full_name":"o/r"}"#.into(),
+        };
+        let url = "https://api.github.com/repos/o/r";
+        write_cached(dir, "token a", url, &cached).unwrap();
+        let read = read_cached(dir, "token a", url).unwrap();
+        assert_eq!((read.etag, read.body), (cached.etag, cached.body));
+        assert!(read_cached(dir, "token a", "https://api.github.com/repos/o/other").is_none());
+        // what one token was shown stays its own
+        assert!(read_cached(dir, "token b", url).is_none());
+        assert!(read_cached(dir, "", url).is_none());
+    }
+
+    #[test]
+    fn tag_path_and_encode_escape_reserved_characters() {
+        assert_eq!(tag_path("v1.0.0"), "v1.0.0");
+        assert_eq!(tag_path("v1.0+build#2"), "v1.0%2Bbuild%232");
+        assert_eq!(tag_path("release/1.0 rc?"), "release/1.0%20rc%3F");
+        assert_eq!(encode("feature/a&b"), "feature%2Fa%26b");
+    }
+
+    #[test]
+    fn newest_tags_query_aliases_every_repo() {
+        let query = newest_tags_query(&["o/r", "other/repo"]);
+        let first = "query { r0: repository(owner: \"o\", name: \"r\") { latestRelease";
+        assert!(query.starts_with(first));
+        assert!(query.contains(" r1: repository(owner: \"other\", name: \"repo\") {"));
+        assert!(query.contains("refs(refPrefix: \"refs/tags/\", first: 30,"));
+    }
+
+    #[test]
+    fn record_rate_limit_sets_gauges_per_resource() {
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert("x-ratelimit-remaining", "4321".parse().unwrap());
+        headers.insert("x-ratelimit-limit", "5000".parse().unwrap());
+        headers.insert("x-ratelimit-resource", "search".parse().unwrap());
+        record_rate_limit(&headers);
+        let gauge = |g: &prometheus::IntGaugeVec| g.with_label_values(&["search"]).get();
+        assert_eq!(gauge(&METRICS.github_rate_limit_remaining), 4321);
+        assert_eq!(gauge(&METRICS.github_rate_limit_limit), 5000);
+    }
+
+    #[test]
+    fn rate_limit_reset_reads_headers() {
+        let now = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-ratelimit-remaining", "0".parse().unwrap());
+        headers.insert("x-ratelimit-reset", "1700000300".parse().unwrap());
+        assert_eq!(
+            rate_limit_reset(StatusCode::FORBIDDEN, &headers, now),
+            DateTime::from_timestamp(1_700_000_300, 0)
+        );
+
+        headers.insert("retry-after", "30".parse().unwrap());
+        assert_eq!(
+            rate_limit_reset(StatusCode::TOO_MANY_REQUESTS, &headers, now),
+            Some(now + Duration::seconds(30))
+        );
+
+        // a 403 that still has quota left is a permissions problem, not throttling
+        let mut headers = HeaderMap::new();
+        headers.insert("x-ratelimit-remaining", "42".parse().unwrap());
+        assert_eq!(rate_limit_reset(StatusCode::FORBIDDEN, &headers, now), None);
+        assert_eq!(rate_limit_reset(StatusCode::OK, &HeaderMap::new(), now), None);
+    }
+}
@@ -0,0 +1,36 @@
+use anyhow::{Context, Result};
+use axum::{extract::State, http::StatusCode, routing::get, Router};
+use chrono::Utc;
+use std::sync::{
+    atomic::{AtomicI64, Ordering},
+    Arc,
+};
+
+use crate::metrics::METRICS;
+
+/// Serves `/healthz` and `/readyz` on `port` until the process exits.
+///
+/// `/readyz` is 503 until a pass has succeeded, and again whenever the last
+/// successful pass is older than `max_age_secs`, which the poll loop updates
+/// when a reloaded config changes the poll intervals.
+pub async fn serve(port: u16, max_age_secs: Arc<AtomicI64>) -> Result<()> {
+    let app = Router::new()
+        .route("/healthz", get(|| async { StatusCode::OK }))
+        .route("/readyz", get(readyz))
+        .with_state(max_age_secs);
+    let listener = tokio::net::TcpListener::bind(("0.0.0.0", port))
+        .await
+        .with_context(|| format!("binding health port {}", port))?;
+    axum::serve(listener, app).await.context("health server")?;
+    Ok(())
+}
+
+async fn readyz(State(max_age_secs): State<Arc<AtomicI64>>) -> StatusCode {
+    // the gauge doubles as the shared "last successful poll" timestamp
+    let last = METRICS.last_successful_poll.get();
+    if last > 0 && Utc::now().timestamp() - last <= max_age_secs.load(Ordering::Relaxed) {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    }
+}
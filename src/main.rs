@@ -1,204 +1,3811 @@
+mod commands;
+mod config;
+mod dashboard;
+mod digest;
+mod docker;
+mod git;
+mod github;
+mod health;
+mod metrics;
+mod notify;
+mod receiver;
+mod state;
+
 use anyhow::{Context, Result};
+use chrono::{DateTime, FixedOffset, NaiveTime, Utc};
 use clap::Parser;
+use commands::Command;
+use config::{Config, RepoConfig};
+use digest::DigestEntry;
+use futures::{future, stream, StreamExt};
+use github::Fetched;
+use metrics::METRICS;
+use notify::{
+    EmailConfig, LinkButton, MatrixConfig, Notifier, NtfyMessage, TelegramConfig, TgParseMode,
+};
 use octocrab::models;
+use rand::Rng;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, fs, path::PathBuf, time::Duration};
-use tokio::time::sleep;
-use tracing::{error, info};
+use state::{QueuedDetection, StateBackend, StateStore};
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    fmt,
+    io::Write,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc, LazyLock, Mutex,
+    },
+    time::Duration,
+};
+use tokio::time::{sleep_until, Instant};
+use tracing::{debug, error, info, instrument, warn};
 use tracing_subscriber::EnvFilter;
 
 #[derive(Parser, Debug, Clone)]
-#[command(name = "github-tag-watcher", author, version, about)]
+#[command(name = "github-tag-watcher", author, version, about, subcommand_negates_reqs = true)]
 struct Args {
-    /// Comma-separated list like: owner1/repo1,owner2/repo2
-    #[arg(long, env = "REPOS")]
-    repos: String,
+    /// Without a subcommand, watch the repos until shut down
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Comma-separated list like: owner1/repo1,owner2/repo2 (owner/* watches every repo of owner)
+    #[arg(long, env = "REPOS", required_unless_present_any = ["config", "repos_file"])]
+    repos: Option<String>,
+
+    /// File with one repo per line (`#` starts a comment), merged with --repos
+    #[arg(long, env = "REPOS_FILE")]
+    repos_file: Option<PathBuf>,
+
+    /// File of `owner/repo` patterns (`*` wildcards) to mute: still checked, never notified
+    #[arg(long, env = "IGNORE_FILE")]
+    ignore_file: Option<PathBuf>,
+
+    /// Registry of `docker://` images that don't name one
+    #[arg(long, env = "DOCKER_REGISTRY", default_value = docker::DOCKER_HUB)]
+    docker_registry: String,
+
+    /// User for the container registry, for private images or higher pull limits
+    #[arg(long, env = "DOCKER_USER", requires = "docker_pass")]
+    docker_user: Option<String>,
+
+    /// Password or access token of --docker-user
+    #[arg(long, env = "DOCKER_PASS", requires = "docker_user", hide_env_values = true)]
+    docker_pass: Option<String>,
+
+    /// How often owner/* entries are re-listed to pick up new repos, in seconds
+    #[arg(long, env = "REPO_REFRESH_SECS", default_value = "3600")]
+    repo_refresh_secs: u64,
 
-    /// Poll interval in seconds
+    /// Leave archived and disabled repos out when expanding owner/*
+    #[arg(long, env = "SKIP_ARCHIVED")]
+    skip_archived: bool,
+
+    /// Keep watching repos under their new name when they are renamed or transferred
+    /// (`--follow-renames false` only warns)
+    #[arg(long, env = "FOLLOW_RENAMES", default_value_t = true, action = clap::ArgAction::Set)]
+    follow_renames: bool,
+
+    /// TOML config file with per-repo settings; takes precedence over --repos
+    #[arg(long, env = "CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Don't check the Telegram token and chats at startup
+    #[arg(long, env = "SKIP_STARTUP_CHECK")]
+    skip_startup_check: bool,
+
+    /// Reload --config when it changes, without restarting
+    #[arg(long, env = "WATCH_CONFIG", requires = "config")]
+    watch_config: bool,
+
+    /// Poll interval in seconds (the config file can override it per repo)
     #[arg(long, env = "POLL_SECS", default_value = "120")]
     poll_secs: u64,
 
+    /// Add a random 0..=N seconds to every wait, so instances started together drift apart
+    #[arg(long, env = "POLL_JITTER", default_value = "0")]
+    poll_jitter_secs: u64,
+
     /// GitHub token (PAT). Optional but recommended.
-    #[arg(long, env = "GITHUB_TOKEN")]
+    #[arg(long, env = "GITHUB_TOKEN", hide_env_values = true)]
     github_token: Option<String>,
 
+    /// Authenticate as this GitHub App instead of with --github-token
+    #[arg(
+        long,
+        env = "GH_APP_ID",
+        requires_all = ["gh_app_private_key_path", "gh_app_installation_id"]
+    )]
+    gh_app_id: Option<u64>,
+
+    /// PEM private key of the GitHub App
+    #[arg(long, env = "GH_APP_PRIVATE_KEY_PATH", requires = "gh_app_id")]
+    gh_app_private_key_path: Option<PathBuf>,
+
+    /// Installation of the GitHub App whose token the bot uses
+    #[arg(long, env = "GH_APP_INSTALLATION_ID", requires = "gh_app_id")]
+    gh_app_installation_id: Option<u64>,
+
+    /// OWNER=TOKEN: use this token for the repos of OWNER instead of the
+    /// default credentials (repeatable, or comma-separated in the env var)
+    #[arg(
+        long = "owner-token",
+        env = "OWNER_TOKENS",
+        value_delimiter = ',',
+        value_parser = parse_owner_token,
+        hide_env_values = true
+    )]
+    owner_tokens: Vec<(String, String)>,
+
+    /// Timeout for every GitHub and notification request (webhooks use --webhook-timeout-secs)
+    #[arg(long, env = "HTTP_TIMEOUT", default_value = "30")]
+    http_timeout_secs: u64,
+
+    /// Retries per GitHub GET on connection errors and 5xx responses
+    #[arg(long, env = "GH_MAX_RETRIES", default_value = "2")]
+    gh_max_retries: u32,
+
+    /// Send GitHub and notification requests through this HTTP(S) proxy, e.g.
+    /// http://proxy.corp:3128 (HTTPS_PROXY and ALL_PROXY are honored without it)
+    #[arg(long, env = "PROXY")]
+    proxy: Option<String>,
+
+    /// GitHub API base URL for GitHub Enterprise Server (e.g., https://github.mycorp.com/api/v3)
+    #[arg(long, env = "GITHUB_BASE_URL")]
+    github_base_url: Option<String>,
+
     /// Telegram bot token (e.g., 123456:ABC-DEF...)
-    #[arg(long, env = "TG_BOT_TOKEN")]
-    tg_bot_token: String,
+    #[arg(long, env = "TG_BOT_TOKEN", hide_env_values = true)]
+    tg_bot_token: Option<String>,
+
+    /// Comma-separated Telegram chat ids (e.g., -1001234567890 for channels/supergroups)
+    #[arg(
+        long = "tg-chat-id",
+        value_name = "TG_CHAT_ID",
+        env = "TG_CHAT_ID",
+        value_delimiter = ',',
+        allow_hyphen_values = true
+    )]
+    tg_chat_ids: Vec<i64>,
+
+    /// Forum topic (message thread) id to post in, for supergroups with topics
+    #[arg(long, env = "TG_THREAD_ID")]
+    tg_thread_id: Option<i64>,
+
+    /// Send a "still watching" message after this many seconds without notifications
+    #[arg(long, env = "HEARTBEAT_SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    heartbeat_secs: Option<u64>,
+
+    /// Telegram chat for the heartbeat, instead of every configured target
+    #[arg(long, env = "HEARTBEAT_CHAT_ID", requires_all = ["heartbeat_secs", "tg_bot_token"])]
+    heartbeat_chat_id: Option<i64>,
+
+    /// Forum topic for the heartbeat in --heartbeat-chat-id
+    #[arg(long, env = "HEARTBEAT_THREAD_ID", requires = "heartbeat_chat_id")]
+    heartbeat_thread_id: Option<i64>,
+
+    /// Send a message naming the version and what is watched when the watcher starts
+    #[arg(long, env = "NOTIFY_ON_START")]
+    notify_on_start: bool,
+
+    /// How Telegram messages are formatted
+    #[arg(long, env = "TG_PARSE_MODE", value_enum, default_value = "markdown_v2")]
+    tg_parse_mode: TgParseMode,
+
+    /// Retries per Telegram request on 429 / 5xx responses
+    #[arg(long, env = "TG_MAX_RETRIES", default_value = "3")]
+    tg_max_retries: u32,
+
+    /// Don't let Telegram render a link preview card under each message
+    #[arg(long, env = "NO_LINK_PREVIEW")]
+    no_link_preview: bool,
+
+    /// Send Telegram messages silently (no sound on recipients' devices)
+    #[arg(long, env = "TG_SILENT")]
+    tg_silent: bool,
+
+    /// Discord webhook URL to also (or instead) send notifications to
+    #[arg(long, env = "DISCORD_WEBHOOK", hide_env_values = true)]
+    discord_webhook: Option<String>,
+
+    /// Slack incoming webhook URL to also (or instead) send notifications to
+    #[arg(long, env = "SLACK_WEBHOOK", hide_env_values = true)]
+    slack_webhook: Option<String>,
+
+    /// SMTP server to send email notifications through
+    #[arg(long, env = "SMTP_HOST", requires_all = ["email_from", "email_to"])]
+    smtp_host: Option<String>,
+
+    /// SMTP port; 465 uses implicit TLS, any other port STARTTLS
+    #[arg(long, env = "SMTP_PORT", default_value = "587")]
+    smtp_port: u16,
+
+    /// SMTP username
+    #[arg(long, env = "SMTP_USER", requires = "smtp_pass")]
+    smtp_user: Option<String>,
+
+    /// SMTP password
+    #[arg(long, env = "SMTP_PASS", hide_env_values = true)]
+    smtp_pass: Option<String>,
+
+    /// Sender address, e.g. "Tag Watcher <watcher@example.com>"
+    #[arg(long, env = "EMAIL_FROM")]
+    email_from: Option<String>,
+
+    /// Comma-separated recipient addresses
+    #[arg(long, env = "EMAIL_TO", value_delimiter = ',')]
+    email_to: Vec<String>,
+
+    /// Microsoft Teams incoming webhook URL to send MessageCards to
+    #[arg(long, env = "TEAMS_WEBHOOK", hide_env_values = true)]
+    teams_webhook: Option<String>,
+
+    /// ntfy server to publish push notifications to
+    #[arg(long, env = "NTFY_SERVER", default_value = "https://ntfy.sh")]
+    ntfy_server: String,
+
+    /// ntfy topic to publish to (subscribe to it in the ntfy app)
+    #[arg(long, env = "NTFY_TOPIC")]
+    ntfy_topic: Option<String>,
+
+    /// Matrix homeserver to send notifications through, e.g. https://matrix.example.com
+    #[arg(long, env = "MATRIX_HOMESERVER", requires_all = ["matrix_token", "matrix_room"])]
+    matrix_homeserver: Option<String>,
+
+    /// Access token of the Matrix account that posts
+    #[arg(long, env = "MATRIX_TOKEN", hide_env_values = true)]
+    matrix_token: Option<String>,
+
+    /// Matrix room to post in (the account must have joined it)
+    #[arg(long, env = "MATRIX_ROOM")]
+    matrix_room: Option<String>,
+
+    /// Also print one line per detection to stdout (logs go to stderr)
+    #[arg(long, env = "STDOUT")]
+    stdout: bool,
+
+    /// Also show a desktop notification per detection (builds with the `desktop` feature)
+    #[cfg(feature = "desktop")]
+    #[arg(long, env = "DESKTOP")]
+    desktop: bool,
+
+    /// Line printed by --stdout; {repo}, {tag}, {url}, {kind} and {title} are filled in
+    #[arg(long, env = "STDOUT_FORMAT", default_value = "{repo} {tag} {url}")]
+    stdout_format: String,
+
+    /// URL to POST a JSON event to for every detection
+    #[arg(long, env = "WEBHOOK_URL", hide_env_values = true)]
+    webhook_url: Option<String>,
+
+    /// Timeout for webhook requests, so a slow endpoint can't stall the poll loop
+    #[arg(long, env = "WEBHOOK_TIMEOUT_SECS", default_value = "10")]
+    webhook_timeout_secs: u64,
+
+    /// Most notifications sent per second over all targets, bursts are queued (0 = no limit)
+    #[arg(long, env = "NOTIFY_RATE_PER_SEC", default_value = "20")]
+    notify_rate_per_sec: f64,
+
+    /// Append every detection as a JSON line to this file
+    #[arg(long, env = "EVENT_LOG")]
+    event_log: Option<PathBuf>,
+
+    /// Skip sending the same tag of a repo to the same target again within this many seconds
+    /// (0 = never skip)
+    #[arg(long, env = "DEDUPE_WINDOW_SECS", default_value = "300")]
+    dedupe_window_secs: u64,
+
+    /// Max number of repos checked at the same time
+    #[arg(long, env = "CONCURRENCY", default_value = "4", value_parser = clap::value_parser!(u64).range(1..))]
+    concurrency: u64,
+
+    /// Only announce tags matching this regex
+    #[arg(long = "tag-include-regex", env = "TAG_INCLUDE", value_parser = Regex::new)]
+    tag_include: Option<Regex>,
+
+    /// Never announce tags matching this regex
+    #[arg(long = "tag-exclude-regex", env = "TAG_EXCLUDE", value_parser = Regex::new)]
+    tag_exclude: Option<Regex>,
+
+    /// Ignore pre-releases and drafts when picking the latest release
+    #[arg(long, env = "STABLE_ONLY", conflicts_with = "prerelease_only")]
+    stable_only: bool,
+
+    /// Only consider pre-releases (e.g. for a separate beta channel)
+    #[arg(long, env = "PRERELEASE_ONLY")]
+    prerelease_only: bool,
+
+    /// Only consider releases published after this time (RFC 3339), even unseen older ones
+    #[arg(long, env = "SINCE", value_parser = parse_since)]
+    since: Option<DateTime<Utc>>,
+
+    /// Consecutive failed checks after which a repo is checked less often
+    #[arg(long, env = "BACKOFF_AFTER", default_value = "3", value_parser = clap::value_parser!(u32).range(1..))]
+    backoff_after: u32,
+
+    /// Longest interval a failing repo backs off to, in seconds
+    #[arg(long, env = "BACKOFF_MAX_SECS", default_value = "3600")]
+    backoff_max_secs: u64,
+
+    /// Start of the daily quiet hours (HH:MM); notifications are queued until --quiet-end
+    #[arg(long, env = "QUIET_START", requires = "quiet_end", value_parser = parse_hhmm)]
+    quiet_start: Option<NaiveTime>,
+
+    /// End of the quiet hours (HH:MM), when queued notifications are sent
+    #[arg(long, env = "QUIET_END", requires = "quiet_start", value_parser = parse_hhmm)]
+    quiet_end: Option<NaiveTime>,
 
-    /// Telegram chat id (e.g., -1001234567890 for channels/supergroups)
-    #[arg(long, env = "TG_CHAT_ID", allow_hyphen_values = true)]
-    tg_chat_id: i64,
+    /// Time zone of the quiet hours: UTC, local (the system's, see TZ) or an offset like +02:00
+    #[arg(long, env = "TIMEZONE", default_value = "UTC", value_parser = parse_timezone)]
+    timezone: Timezone,
 
-    /// Path to state file
+    /// Most notifications kept queued per repo (quiet hours or failed deliveries); the oldest
+    /// are dropped beyond it
+    #[arg(long, env = "MAX_QUEUED", default_value = "100", value_parser = clap::value_parser!(u64).range(1..))]
+    max_queued: u64,
+
+    /// After notifying about a repo, hold its further detections back this many seconds,
+    /// then send one message for the newest
+    #[arg(long, env = "NOTIFY_COOLDOWN_SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    notify_cooldown_secs: Option<u64>,
+
+    /// Collect the detections of a pass and send each target one message listing them
+    #[arg(long, env = "DIGEST")]
+    digest: bool,
+
+    /// Follow the release marked "Latest" on GitHub instead of the newest releases
+    #[arg(long, env = "LATEST_POINTER")]
+    latest_pointer: bool,
+
+    /// What makes a release the newest: created (GitHub's order), semver or published
+    #[arg(long, env = "ORDER_BY", value_enum, default_value = "created")]
+    order_by: OrderBy,
+
+    /// Max number of missed tags to announce for one repo in a single pass
+    #[arg(long, env = "MAX_CATCHUP", default_value = "10", value_parser = clap::value_parser!(u64).range(1..))]
+    max_catchup: u64,
+
+    /// Mark released and tagged versions as verified or unsigned by their tag's signature
+    /// (one or two extra GitHub requests per detection)
+    #[arg(long, env = "SHOW_VERIFICATION")]
+    show_verification: bool,
+
+    /// Releases, tags or commits fetched per request, newest first (GitHub allows up to 100);
+    /// 1 by default, or 30 when catch-up, --order-by, --since or retraction notices are on
+    #[arg(long, env = "PAGE_SIZE", value_parser = clap::value_parser!(u8).range(1..=100))]
+    page_size: Option<u8>,
+
+    /// Ask GraphQL for the newest release and tag of many repos at once, and skip the REST
+    /// requests of repos where nothing changed
+    #[arg(long, env = "USE_GRAPHQL")]
+    use_graphql: bool,
+
+    /// Chat message to send instead of the default; {repo}, {tag}, {url}, {prev_tag},
+    /// {owner}, {name}, {kind} and {emoji} are filled in
+    #[arg(long, env = "MSG_TEMPLATE")]
+    message_template: Option<String>,
+
+    /// Leave out the 🚀 at the start of messages (and of {emoji} in templates)
+    #[arg(long, env = "NO_EMOJI")]
+    no_emoji: bool,
+
+    /// Include the release notes in the message (release detections only)
+    #[arg(long, env = "NOTES")]
+    include_notes: bool,
+
+    /// Show a release's title before its tag when they differ, e.g. "2024.1 LTS (v2024.1)"
+    #[arg(long, env = "RELEASE_NAMES")]
+    release_names: bool,
+
+    /// Truncate release notes to this many characters
+    #[arg(long, env = "NOTES_MAX_CHARS", default_value = "1500")]
+    notes_max_chars: usize,
+
+    /// Add a Telegram button linking to each release asset (up to 8)
+    #[arg(long, env = "ASSET_BUTTONS")]
+    asset_buttons: bool,
+
+    /// Log messages instead of sending them (state is still updated)
+    #[arg(long, env = "DRY_RUN")]
+    dry_run: bool,
+
+    /// Serve Prometheus metrics on this port at /metrics
+    #[arg(long, env = "METRICS_PORT")]
+    metrics_port: Option<u16>,
+
+    /// Serve /healthz and /readyz probes on this port
+    #[arg(long, env = "HEALTH_PORT")]
+    health_port: Option<u16>,
+
+    /// Serve an HTML table of the watched repos and their state on this port
+    #[arg(long, env = "DASHBOARD_PORT")]
+    dashboard_port: Option<u16>,
+
+    /// Require `Authorization: Bearer <token>` on the dashboard
+    #[arg(long, env = "DASHBOARD_TOKEN", requires = "dashboard_port", hide_env_values = true)]
+    dashboard_token: Option<String>,
+
+    /// When the state file is missing or empty, record the current tags without notifying
+    #[arg(long, env = "SEED_ON_EMPTY")]
+    seed_on_empty: bool,
+
+    /// Record the current version of every repo without state silently, not just on an empty state
+    #[arg(long, env = "SEED_NEW_REPOS")]
+    seed_new_repos: bool,
+
+    /// Send a notice when the last seen tag or release is deleted, and roll the state back
+    #[arg(long, env = "NOTIFY_ON_RETRACTION")]
+    notify_on_retraction: bool,
+
+    /// Do a single pass over all repos, save state and exit
+    #[arg(long, env = "ONCE")]
+    once: bool,
+
+    /// Directory to cache GitHub responses in, so repeated --once runs can send conditional
+    /// requests for repo lookups and owner listings too
+    #[arg(long, env = "CACHE_DIR")]
+    cache_dir: Option<PathBuf>,
+
+    /// Exit as soon as a pass had a failed repo check, instead of carrying on
+    #[arg(long, env = "FAIL_FAST")]
+    fail_fast: bool,
+
+    /// Log more: -v info, -vv debug, -vvv trace (overrides RUST_LOG)
+    #[arg(short, long, action = clap::ArgAction::Count, global = true)]
+    verbose: u8,
+
+    /// Log output format
+    #[arg(long, env = "LOG_FORMAT", value_enum, default_value = "text")]
+    log_format: LogFormat,
+
+    /// Path to state file (or database, with --state-backend sqlite)
     #[arg(long, env = "STATE_PATH", default_value = "state.json")]
     state_path: PathBuf,
+
+    /// Keep this many previous versions of the JSON state file (state.json.1, .2, ...)
+    #[arg(long, env = "STATE_BACKUP_COUNT", default_value = "0")]
+    state_backup_count: usize,
+
+    /// Keep the state of repos that are no longer watched instead of pruning it at startup
+    #[arg(long, env = "KEEP_ORPHANS")]
+    keep_orphans: bool,
+
+    /// How state is persisted
+    #[arg(long, env = "STATE_BACKEND", value_enum, default_value = "json")]
+    state_backend: StateBackend,
 }
 
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
-struct State {
-    /// repo -> last_seen_tag
-    last_seen: HashMap<String, String>,
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum LogFormat {
+    /// Human-readable lines
+    Text,
+    /// One JSON object per line, with fields like `repo` kept structured
+    Json,
 }
 
-impl State {
-    fn load(p: &PathBuf) -> Result<Self> {
-        if p.exists() {
-            let s = fs::read_to_string(p)
-                .with_context(|| format!("reading state file {}", p.display()))?;
-            Ok(serde_json::from_str(&s).context("parsing state json")?)
-        } else {
-            Ok(Self::default())
-        }
+/// Exit codes besides 0, so supervisors and CI can tell failures apart.
+/// Attached to errors as context; anything untagged exits with `Failure`.
+/// Invalid flags exit with 2 from clap itself, like `Config`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Exit {
+    Failure = 1,
+    /// Invalid repo list, config file, GitHub credentials or proxy
+    Config = 2,
+    /// The state file or database can't be opened / read
+    State = 3,
+    /// Telegram rejected the token or a chat at startup
+    StartupCheck = 4,
+    /// Repo checks failed: all of them with --once, any with --fail-fast
+    ChecksFailed = 5,
+}
+
+impl fmt::Display for Exit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Exit::Failure => "failed",
+            Exit::Config => "invalid configuration",
+            Exit::State => "loading the state failed",
+            Exit::StartupCheck => "Telegram startup check failed (--skip-startup-check skips it)",
+            Exit::ChecksFailed => "repo checks failed",
+        })
+    }
+}
+
+impl Exit {
+    /// The exit status for an error out of [`run`]: the `Exit` it was
+    /// tagged with, else `Failure`.
+    fn of(e: &anyhow::Error) -> Exit {
+        e.downcast_ref::<Exit>().copied().unwrap_or(Exit::Failure)
     }
-    fn save(&self, p: &PathBuf) -> Result<()> {
-        let tmp = format!("{}.tmp", p.display());
-        fs::write(&tmp, serde_json::to_vec_pretty(self)?)
-            .with_context(|| format!("writing tmp state {}", tmp))?;
-        fs::rename(&tmp, p).with_context(|| format!("replacing state {}", p.display()))?;
-        Ok(())
+}
+
+/// Env vars whose value may instead be read from the file `<VAR>_FILE` names,
+/// like Docker and Kubernetes secrets are mounted.
+const SECRET_ENVS: &[&str] = &[
+    "GITHUB_TOKEN",
+    "OWNER_TOKENS",
+    "TG_BOT_TOKEN",
+    "DISCORD_WEBHOOK",
+    "SLACK_WEBHOOK",
+    "TEAMS_WEBHOOK",
+    "SMTP_PASS",
+    "MATRIX_TOKEN",
+    "WEBHOOK_URL",
+    "WEBHOOK_SECRET",
+    "DASHBOARD_TOKEN",
+    "DOCKER_PASS",
+];
+
+/// The secrets set through `<VAR>_FILE` (looked up with `var`), read from
+/// their files with surrounding whitespace trimmed.
+fn secret_files(
+    var: impl Fn(&str) -> Option<std::ffi::OsString>,
+) -> Result<Vec<(&'static str, String)>> {
+    let mut secrets = Vec::new();
+    for &name in SECRET_ENVS {
+        let Some(path) = var(&format!("{name}_FILE")) else {
+            continue;
+        };
+        let path = PathBuf::from(path);
+        let value = std::fs::read_to_string(&path)
+            .with_context(|| format!("reading {name}_FILE {}", path.display()))?;
+        secrets.push((name, value.trim().to_string()));
     }
+    Ok(secrets)
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    tracing_subscriber::fmt()
-        .with_env_filter(EnvFilter::from_default_env().add_directive("info".parse().unwrap()))
-        .init();
+fn main() -> ExitCode {
+    // `<VAR>_FILE` takes the place of `<VAR>` before clap reads the environment,
+    // and before the runtime starts threads that could read it concurrently
+    match secret_files(|var| std::env::var_os(var)) {
+        Ok(secrets) => {
+            for (name, value) in secrets {
+                std::env::set_var(name, value);
+            }
+        }
+        Err(e) => {
+            eprintln!("Error: {e:?}");
+            return ExitCode::from(Exit::Config as u8);
+        }
+    }
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("building the tokio runtime")
+        .block_on(start())
+}
 
+async fn start() -> ExitCode {
     let args = Args::parse();
-    info!("starting with repos: {}", args.repos);
+    let filter = match args.verbose {
+        0 => EnvFilter::from_default_env().add_directive("info".parse().unwrap()),
+        1 => EnvFilter::new("info"),
+        2 => EnvFilter::new("debug"),
+        _ => EnvFilter::new("trace"),
+    };
+    match args.log_format {
+        // stderr, so stdout only carries --stdout detections and command output
+        LogFormat::Text => {
+            tracing_subscriber::fmt().with_writer(std::io::stderr).with_env_filter(filter).init()
+        }
+        LogFormat::Json => tracing_subscriber::fmt()
+            .json()
+            .with_writer(std::io::stderr)
+            .with_env_filter(filter)
+            .init(),
+    }
+
+    match run(args).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {e:?}");
+            ExitCode::from(Exit::of(&e) as u8)
+        }
+    }
+}
 
-    let mut state = State::load(&args.state_path).unwrap_or_default();
+async fn run(args: Args) -> Result<()> {
+    // one client for GitHub and every notification, so connections and TLS
+    // sessions are reused; a hung request fails the check instead of the loop
+    let client = proxied(reqwest::Client::builder(), &args)
+        .and_then(|builder| {
+            let timeout = Duration::from_secs(args.http_timeout_secs);
+            Ok(builder.timeout(timeout).build()?)
+        })
+        .context(Exit::Config)?;
+    notify::limit_rate(args.notify_rate_per_sec);
+    if let Some(dir) = &args.cache_dir {
+        github::cache_responses(dir)
+            .with_context(|| format!("creating cache dir {}", dir.display()))
+            .context(Exit::Config)?;
+    }
 
-    let octo = if let Some(token) = &args.github_token {
-        octocrab::OctocrabBuilder::new()
-            .personal_token(token.clone())
-            .build()?
-    } else {
-        octocrab::Octocrab::builder().build()?
+    match &args.command {
+        Some(Command::TestNotify) => commands::test_notify(&args, &client).await,
+        Some(Command::ListState { json }) => commands::list_state(&args, *json),
+        Some(Command::ResetState { repo, yes }) => {
+            commands::reset_state(&args, repo.as_deref(), *yes)
+        }
+        Some(Command::Serve {
+            listen_addr,
+            webhook_secret,
+        }) => receiver::serve(&args, client, *listen_addr, webhook_secret).await,
+        None => watch(args, client).await,
+    }
+}
+
+/// The poll loop: checks every repo on its schedule until shut down (or once
+/// with `--once`).
+async fn watch(args: Args, client: reqwest::Client) -> Result<()> {
+    let mut configured = resolve_repos(&args).context(Exit::Config)?;
+    info!(
+        repos = %configured.iter().map(|r| r.name.as_str()).collect::<Vec<_>>().join(","),
+        "starting"
+    );
+    startup_check(&configured, &client, &args).await?;
+
+    // held until `watch` returns
+    let _lock = state::lock(args.state_backend, &args.state_path).context(Exit::State)?;
+    let state = state::open(args.state_backend, &args.state_path, args.state_backup_count)
+        .context(Exit::State)?;
+    // repos seen for the first time are recorded silently for the whole run,
+    // so one that failed during the first pass isn't announced on the next
+    let seed = args.seed_on_empty && !state.has_seen_tags().context(Exit::State)?;
+    if seed {
+        info!("state is empty, seeding current tags without notifying");
+    }
+    let state = Arc::new(Mutex::new(state));
+
+    let octo = github_clients(&args, &client).await.context(Exit::Config)?;
+
+    if let Some(port) = args.metrics_port {
+        tokio::spawn(async move {
+            if let Err(e) = metrics::serve(port).await {
+                error!(error=?e, "metrics server failed");
+            }
+        });
+    }
+
+    // how stale the last successful pass may get before /readyz fails
+    let ready_max_age = Arc::new(AtomicI64::new(ready_max_age_secs(&configured, &args)));
+    if let Some(port) = args.health_port {
+        let max_age_secs = ready_max_age.clone();
+        tokio::spawn(async move {
+            if let Err(e) = health::serve(port, max_age_secs).await {
+                error!(error=?e, "health server failed");
+            }
+        });
+    }
+
+    let mut repos = expand_repos(&configured, &octo, &state, &args).await?;
+    // what the dashboard lists, repos that weren't checked yet included
+    let watched = Arc::new(Mutex::new(repo_names(&repos)));
+    if let Some(port) = args.dashboard_port {
+        let (token, state) = (args.dashboard_token.clone(), state.clone());
+        let watched = watched.clone();
+        tokio::spawn(async move {
+            if let Err(e) = dashboard::serve(port, token, state, watched).await {
+                error!(error=?e, "dashboard server failed");
+            }
+        });
+    }
+    let mut expanded_at = Instant::now();
+    if !args.keep_orphans {
+        let mut store = state.lock().expect("state lock poisoned");
+        prune_orphans(store.as_mut(), &repos).context(Exit::State)?;
+    }
+    if args.notify_on_start && !args.once && !quiet_now(&args) {
+        send_startup(repos.len(), &client, &args).await;
+    }
+    // renames are picked up on the same schedule as new wildcard repos
+    let wants_refresh =
+        |configured: &[RepoConfig]| configured.iter().any(|r| r.wildcard_owner().is_some());
+    let mut refresh = args.follow_renames || wants_refresh(&configured);
+    let (_config_watcher, mut config_events) = match &args.config {
+        Some(path) if args.watch_config => {
+            let (watcher, events) = config::watch(path).context(Exit::Config)?;
+            (Some(watcher), Some(events))
+        }
+        _ => (None, None),
     };
+    // set by a config change until the reloaded config is in use
+    let mut reload = false;
 
-    let repos: Vec<String> = args
-        .repos
-        .split(',')
-        .map(|s| s.trim().to_string())
-        .filter(|s| !s.is_empty())
-        .collect();
+    // repos missing here (new ones included) are due right away
+    let mut next_check: HashMap<String, Instant> = HashMap::new();
+    // any delivered notification pushes the heartbeat back
+    let heartbeat_interval = Duration::from_secs(args.heartbeat_secs.unwrap_or_default());
+    let mut notified_total = METRICS.notifications_total();
+    let mut heartbeat_at = Instant::now() + heartbeat_interval;
+
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
 
     loop {
-        for repo in &repos {
-            if let Err(e) = check_repo(repo, &octo, &mut state, &args).await {
-                error!(%repo, error=?e, "repo check failed");
+        if reload {
+            // let the writes of one save settle before reading the file
+            tokio::time::sleep(CONFIG_SETTLE).await;
+        }
+        while config_events.as_mut().is_some_and(|events| events.try_recv().is_ok()) {
+            reload = true;
+        }
+        if reload {
+            match resolve_repos(&args) {
+                Ok(reloaded) => {
+                    info!(repos = reloaded.len(), "config changed, reloading");
+                    // the running set and the config it came from change together
+                    match expand_repos(&reloaded, &octo, &state, &args).await {
+                        Ok(expanded) => {
+                            repos = expanded;
+                            *watched.lock().expect("repo list lock poisoned") = repo_names(&repos);
+                            refresh = args.follow_renames || wants_refresh(&reloaded);
+                            let max_age = ready_max_age_secs(&reloaded, &args);
+                            ready_max_age.store(max_age, Ordering::Relaxed);
+                            configured = reloaded;
+                            expanded_at = Instant::now();
+                            reload = false;
+                        }
+                        Err(e) => {
+                            warn!(error=?e, "expanding the reloaded config failed, retrying after the next pass")
+                        }
+                    }
+                }
+                Err(e) => {
+                    error!(error=?e, "invalid config, keeping the previous one");
+                    reload = false;
+                }
+            }
+        }
+        if refresh && expanded_at.elapsed() >= Duration::from_secs(args.repo_refresh_secs) {
+            match expand_repos(&configured, &octo, &state, &args).await {
+                Ok(expanded) => {
+                    repos = expanded;
+                    *watched.lock().expect("repo list lock poisoned") = repo_names(&repos);
+                }
+                Err(e) => warn!(error=?e, "refreshing the repo list failed, keeping the previous one"),
             }
+            expanded_at = Instant::now();
         }
-        // Persist state after each full pass
-        if let Err(e) = state.save(&args.state_path) {
+        let now = Instant::now();
+        let due: Vec<RepoConfig> = repos
+            .iter()
+            .filter(|r| next_check.get(&r.name).is_none_or(|&at| at <= now))
+            .cloned()
+            .collect();
+        if !quiet_now(&args) {
+            flush_queued(&repos, &client, &state, &args).await;
+            send_held(&repos, &client, &state, &args).await;
+        }
+        let pass = tokio::select! {
+            pass = run_pass(&due, &client, &octo, &state, &args, seed) => pass,
+            _ = &mut shutdown => break,
+        };
+        // Persist state after each pass
+        if let Err(e) = state.lock().expect("state lock poisoned").flush() {
             error!(error=?e, "state save failed");
         }
-        sleep(Duration::from_secs(args.poll_secs)).await;
+        if args.fail_fast && pass.failed > 0 {
+            let e = anyhow::anyhow!("{} of {} repo checks failed", pass.failed, due.len());
+            return Err(e.context(Exit::ChecksFailed));
+        }
+        if args.once {
+            // only a total failure is worth a non-zero exit for the scheduler
+            if !due.is_empty() && pass.failed == due.len() {
+                let e = anyhow::anyhow!("all {} repo checks failed", pass.failed);
+                return Err(e.context(Exit::ChecksFailed));
+            }
+            return Ok(());
+        }
+        if args.heartbeat_secs.is_some() {
+            let total = METRICS.notifications_total();
+            if total != notified_total {
+                notified_total = total;
+                heartbeat_at = Instant::now() + heartbeat_interval;
+            } else if Instant::now() >= heartbeat_at && !quiet_now(&args) {
+                send_heartbeat(repos.len(), &client, &args).await;
+                heartbeat_at = Instant::now() + heartbeat_interval;
+            }
+        }
+        let checked_at = Instant::now();
+        match pass.rate_limited_until {
+            Some(reset_at) => {
+                // everything in this pass may have been skipped, retry it all after the reset
+                warn!(%reset_at, "GitHub rate limit hit, pausing until reset");
+                let resume = checked_at + (reset_at - Utc::now()).to_std().unwrap_or_default();
+                for repo in &due {
+                    next_check.insert(repo.name.clone(), resume);
+                }
+            }
+            None => {
+                let store = state.lock().expect("state lock poisoned");
+                for repo in &due {
+                    let failures = match store.get(&repo.name) {
+                        Ok(r) => r.map_or(0, |r| r.consecutive_failures),
+                        Err(e) => {
+                            warn!(repo = %repo.name, error=?e, "reading failure count failed");
+                            0
+                        }
+                    };
+                    let interval = backoff_secs(
+                        repo.poll_secs(args.poll_secs),
+                        failures,
+                        args.backoff_after,
+                        args.backoff_max_secs,
+                    );
+                    next_check.insert(repo.name.clone(), checked_at + Duration::from_secs(interval));
+                }
+            }
+        }
+        let wake = repos
+            .iter()
+            .filter_map(|r| next_check.get(&r.name))
+            .min()
+            .copied()
+            .unwrap_or(checked_at + Duration::from_secs(args.poll_secs));
+        tokio::select! {
+            _ = sleep_until(wake + jitter(args.poll_jitter_secs)) => {}
+            _ = config_changed(config_events.as_mut()) => reload = true,
+            _ = &mut shutdown => break,
+        }
     }
+
+    // State is updated after every delivered notification, so saving here
+    // keeps whatever an interrupted pass already announced.
+    info!("received shutdown, saving state and exiting");
+    state.lock().expect("state lock poisoned").flush()?;
+    Ok(())
 }
 
-async fn check_repo(
-    repo: &str,
-    octo: &octocrab::Octocrab,
-    state: &mut State,
+/// Sends "still watching N repos" to --heartbeat-chat-id, else to every chat
+/// target (webhooks and --stdout aren't bothered). Failures are only logged.
+async fn send_heartbeat(repos: usize, client: &reqwest::Client, args: &Args) {
+    let entry = RepoConfig {
+        chat_id: args.heartbeat_chat_id.map(config::ChatIds::One),
+        thread_id: args.heartbeat_thread_id,
+        ..RepoConfig::new("heartbeat")
+    };
+    let only_telegram = args.heartbeat_chat_id.is_some();
+    send_status(&entry, only_telegram, "heartbeat", client, args, |notifier| {
+        heartbeat_message(repos, args, notifier)
+    })
+    .await;
+}
+
+/// Sends "started, watching N repos" to every chat target, for --notify-on-start.
+async fn send_startup(repos: usize, client: &reqwest::Client, args: &Args) {
+    let entry = RepoConfig::new("startup");
+    send_status(&entry, false, "startup message", client, args, |notifier| {
+        startup_message(repos, args, notifier)
+    })
+    .await;
+}
+
+/// Sends a message about the bot itself to `entry`'s chat targets, only the
+/// Telegram ones with `only_telegram`. Failures are only logged.
+async fn send_status(
+    entry: &RepoConfig,
+    only_telegram: bool,
+    what: &str,
+    client: &reqwest::Client,
     args: &Args,
-) -> Result<()> {
-    let (owner, name) = repo
-        .split_once('/')
-        .context("repo must be owner/repo")?;
+    message: impl Fn(&Notifier) -> String,
+) {
+    let notifiers = notifiers_for(entry, args).into_iter().filter(|n| match n {
+        Notifier::Telegram { .. } => true,
+        Notifier::Webhook { .. } | Notifier::Stdout => false,
+        _ => !only_telegram,
+    });
+    for notifier in notifiers {
+        match notifier.send(client, message(&notifier), &[], args.dry_run).await {
+            Ok(()) => debug!(target = %notifier, "{what} sent"),
+            Err(e) => warn!(target = %notifier, error=?e, "sending the {what} failed"),
+        }
+    }
+}
 
-    // Strategy: prefer latest release (if any), else latest tag.
-    let latest = match latest_release_tag(octo, owner, name).await {
-        Ok(tag) => tag,
-        Err(_) => latest_raw_tag(octo, owner, name).await?,
-    };
-
-    let last_seen = state.last_seen.get(repo).cloned();
-    match last_seen {
-        Some(ref t) if t == &latest => {
-            // no change
-            Ok(())
-        }
-        _ => {
-            info!(%repo, %latest, "new tag detected");
-            notify_telegram(
-                &args.tg_bot_token,
-                args.tg_chat_id,
-                format!(
-                    "🚀 New tag in *{repo}*: `{latest}`\nhttps://github.com/{repo}/releases/tag/{latest}"
-                ),
-            )
-                .await?;
-            state.last_seen.insert(repo.to_string(), latest);
-            Ok(())
+fn heartbeat_message(repos: usize, args: &Args, notifier: &Notifier) -> String {
+    let icon = if args.no_emoji { "" } else { "\u{1F493} " };
+    let repos = repo_count(repos);
+    let text = format!("{icon}Still watching {repos}, no changes");
+    status_message("Still watching", &format!("Still watching {repos}"), text, notifier)
+}
+
+/// Names the binary's version, so the message confirms which build is running.
+fn startup_message(repos: usize, args: &Args, notifier: &Notifier) -> String {
+    let icon = if args.no_emoji { "" } else { "\u{1F7E2} " };
+    let (name, version) = (env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+    let text = format!(
+        "{icon}{name} {version} started, watching {}, polling every {}s",
+        repo_count(repos),
+        args.poll_secs
+    );
+    let subject = format!("{name} {version} started");
+    status_message(&format!("{name} started"), &subject, text, notifier)
+}
+
+fn repo_count(repos: usize) -> String {
+    match repos {
+        1 => "1 repo".to_string(),
+        n => format!("{n} repos"),
+    }
+}
+
+/// `text` in `notifier`'s format, under `title` where it has one (`subject`
+/// for email).
+fn status_message(title: &str, subject: &str, text: String, notifier: &Notifier) -> String {
+    match notifier {
+        Notifier::Teams { .. } => serde_json::json!({
+            "@type": "MessageCard",
+            "@context": "https://schema.org/extensions",
+            "summary": title,
+            "text": text,
+        })
+        .to_string(),
+        _ if notifier.takes_ntfy_message() => {
+            let message = NtfyMessage {
+                title: title.into(),
+                message: text,
+                click: String::new(),
+            };
+            serde_json::to_string(&message).expect("ntfy message serializes")
         }
+        Notifier::Email { .. } => format!("{subject}\n{text}"),
+        _ => escape_for(notifier)(&text),
     }
 }
 
-async fn latest_release_tag(
-    octo: &octocrab::Octocrab,
-    owner: &str,
-    repo: &str,
-) -> Result<String> {
-    // list releases: newest first by creation date
-    let releases: Vec<models::repos::Release> = octo
-        .repos(owner, repo)
-        .releases()
-        .list()
-        .per_page(1)
-        .send()
-        .await?
-        .items;
+/// Twice the slowest poll interval among `repos`, the age past which the
+/// last successful pass makes `/readyz` fail.
+fn ready_max_age_secs(repos: &[RepoConfig], args: &Args) -> i64 {
+    let slowest = repos.iter().map(|r| r.poll_secs(args.poll_secs)).max();
+    2 * slowest.unwrap_or(args.poll_secs) as i64
+}
+
+/// Resolves once the config watcher reports a change to `--config`; never
+/// resolves without --watch-config.
+async fn config_changed(events: Option<&mut tokio::sync::mpsc::UnboundedReceiver<()>>) {
+    let Some(events) = events else {
+        return future::pending().await;
+    };
+    if events.recv().await.is_none() {
+        future::pending().await
+    }
+}
 
-    let tag = releases
-        .first()
-        .map(|r| r.tag_name.clone())
-        .context("no releases found with tag_name")?;
+/// How long --watch-config waits after a change before reading the config.
+const CONFIG_SETTLE: Duration = Duration::from_millis(500);
 
-    Ok(tag)
+/// A random delay in `[0, max_secs]` seconds.
+fn jitter(max_secs: u64) -> Duration {
+    if max_secs == 0 {
+        return Duration::ZERO;
+    }
+    Duration::from_secs(rand::thread_rng().gen_range(0..=max_secs))
 }
 
-async fn latest_raw_tag(
-    octo: &octocrab::Octocrab,
-    owner: &str,
-    repo: &str,
-) -> Result<String> {
-    // list tags: GitHub returns most recent commit/tag first
-    // (Note: this is not strictly guaranteed to be semver-highest)
-    let tags = octo
-        .repos(owner, repo)
-        .list_tags()
-        .per_page(1)
-        .send()
-        .await?
-        .items;
-
-    let tag = tags
-        .first()
-        .map(|t| t.name.clone())
-        .context("no raw tags found")?;
-
-    Ok(tag)
-}
-
-async fn notify_telegram(bot_token: &str, chat_id: i64, text: String) -> Result<()> {
-    // Telegram expects MarkdownV2 or HTML – we use MarkdownV2-safe escaping for backticks
-    let url = format!("https://api.telegram.org/bot{}/sendMessage", bot_token);
-    let client = reqwest::Client::new();
-    let payload = serde_json::json!({
-        "chat_id": chat_id,
-        "text": text,
-        "parse_mode": "Markdown"
-    });
+/// Resolves on SIGINT (Ctrl-C) or, on unix, SIGTERM.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            error!(error=?e, "listening for ctrl-c failed");
+            future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut sig) => {
+                sig.recv().await;
+            }
+            Err(e) => {
+                error!(error=?e, "listening for SIGTERM failed");
+                future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = future::pending::<()>();
 
-    let resp = client.post(url).json(&payload).send().await?;
-    let status = resp.status();
-    if !status.is_success() {
-        let body = resp.text().await.unwrap_or_default();
-        anyhow::bail!("telegram send failed: {} body={}", status, body);
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
     }
-    Ok(())
-}
\ No newline at end of file
+}
+
+/// What the checks of one pass share besides the clients and the state.
+struct PassContext {
+    /// Record the newest tag of repos without state instead of announcing it
+    seed: bool,
+    /// Detections collected for --digest, sent once every repo was checked
+    digest: Option<Mutex<Vec<DigestEntry>>>,
+    /// Within --quiet-start/--quiet-end: detections are queued in the state
+    quiet: bool,
+    /// Patterns of --ignore-file, as of the start of the pass
+    muted: Vec<String>,
+    /// Newest releases and tags per repo from --use-graphql; repos missing
+    /// here are checked over REST alone
+    newest: HashMap<String, github::NewestTags>,
+}
+
+/// Sends what was queued during quiet hours or after a failed delivery,
+/// oldest first per repo. A detection that can't be delivered stays queued
+/// (with the ones after it) for the next pass.
+async fn flush_queued(
+    repos: &[RepoConfig],
+    client: &reqwest::Client,
+    state: &Mutex<Box<dyn StateStore>>,
+    args: &Args,
+) {
+    for entry in repos {
+        let repo = entry.name.as_str();
+        let queued = match state.lock().expect("state lock poisoned").get(repo) {
+            Ok(r) => r.map(|r| r.queued).unwrap_or_default(),
+            Err(e) => {
+                warn!(%repo, error=?e, "reading queued notifications failed");
+                continue;
+            }
+        };
+        if queued.is_empty() {
+            continue;
+        }
+        info!(%repo, count = queued.len(), "sending queued notifications");
+        let notifiers = notifiers_for(entry, args);
+        let mut sent = 0;
+        for detection in queued {
+            let candidate = Candidate::from(detection);
+            let notifiers = route(entry, &candidate, &notifiers, args);
+            if let Err(e) = notify_all(repo, &notifiers, &candidate, client, args).await {
+                error!(%repo, tag = %candidate.tag, error=?e, "sending a queued notification failed");
+                break;
+            }
+            sent += 1;
+        }
+        if sent == 0 {
+            continue;
+        }
+        let res = state.lock().expect("state lock poisoned").update(repo, &mut |r| {
+            r.queued.drain(..sent.min(r.queued.len()));
+            r.last_notified_at = Some(Utc::now());
+        });
+        if let Err(e) = res {
+            error!(%repo, error=?e, "saving state after sending queued notifications failed");
+        }
+    }
+}
+
+/// Sends each repo's detection held back by --notify-cooldown-secs once the
+/// cooldown since its last notification is over. One that can't be
+/// delivered stays held for the next pass.
+async fn send_held(
+    repos: &[RepoConfig],
+    client: &reqwest::Client,
+    state: &Mutex<Box<dyn StateStore>>,
+    args: &Args,
+) {
+    let Some(cooldown) = args.notify_cooldown_secs else {
+        return;
+    };
+    for entry in repos {
+        let repo = entry.name.as_str();
+        let (held, last_notified_at) = match state.lock().expect("state lock poisoned").get(repo) {
+            Ok(r) => r.map(|r| (r.held, r.last_notified_at)).unwrap_or_default(),
+            Err(e) => {
+                warn!(%repo, error=?e, "reading the held notification failed");
+                continue;
+            }
+        };
+        let Some(detection) = held else {
+            continue;
+        };
+        if in_cooldown(last_notified_at, cooldown, Utc::now()) {
+            continue;
+        }
+        let candidate = Candidate::from(detection);
+        info!(%repo, tag = %candidate.tag, "cooldown over, sending the newest held detection");
+        let notifiers = notifiers_for(entry, args);
+        let notifiers = route(entry, &candidate, &notifiers, args);
+        if let Err(e) = notify_all(repo, &notifiers, &candidate, client, args).await {
+            error!(%repo, tag = %candidate.tag, error=?e, "sending a held notification failed");
+            continue;
+        }
+        let res = state.lock().expect("state lock poisoned").update(repo, &mut |r| {
+            r.held = None;
+            r.last_notified_at = Some(Utc::now());
+        });
+        if let Err(e) = res {
+            error!(%repo, error=?e, "saving state after sending a held notification failed");
+        }
+    }
+}
+
+/// Whether less than `cooldown_secs` passed since the repo's last notification.
+fn in_cooldown(
+    last_notified_at: Option<DateTime<Utc>>,
+    cooldown_secs: u64,
+    now: DateTime<Utc>,
+) -> bool {
+    last_notified_at.is_some_and(|t| now - t < chrono::Duration::seconds(cooldown_secs as i64))
+}
+
+/// How releases are ordered newest first, for --order-by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum OrderBy {
+    /// As GitHub lists them, by creation date
+    Created,
+    /// By the version in the tag; non-version tags go last
+    Semver,
+    /// By `published_at`; unpublished drafts go last
+    Published,
+}
+
+/// Sorts `items` newest first for `order` (a stable sort, so ties keep
+/// GitHub's order).
+fn order_releases<T>(
+    items: &mut [T],
+    order: OrderBy,
+    tag: impl Fn(&T) -> &str,
+    published_at: impl Fn(&T) -> Option<DateTime<Utc>>,
+) {
+    match order {
+        OrderBy::Created => {}
+        OrderBy::Semver => items.sort_by_key(|t| std::cmp::Reverse(parse_version(tag(t)))),
+        OrderBy::Published => items.sort_by_key(|t| std::cmp::Reverse(published_at(t))),
+    }
+}
+
+/// Time zone for --timezone.
+#[derive(Debug, Clone, Copy)]
+enum Timezone {
+    Utc,
+    /// The system's, as chrono reads it (honours `TZ`)
+    Local,
+    Fixed(FixedOffset),
+}
+
+fn parse_timezone(s: &str) -> Result<Timezone, String> {
+    match s.to_ascii_lowercase().as_str() {
+        "utc" | "z" => Ok(Timezone::Utc),
+        "local" => Ok(Timezone::Local),
+        _ => s
+            .parse::<FixedOffset>()
+            .map(Timezone::Fixed)
+            .map_err(|_| format!("expected UTC, local or an offset like +02:00, got {s:?}")),
+    }
+}
+
+fn parse_owner_token(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((owner, token)) if !owner.is_empty() && !token.is_empty() => {
+            Ok((owner.to_ascii_lowercase(), token.to_string()))
+        }
+        _ => Err("expected OWNER=TOKEN".to_string()),
+    }
+}
+
+fn parse_since(s: &str) -> Result<DateTime<Utc>, String> {
+    DateTime::parse_from_rfc3339(s)
+        .map(|t| t.with_timezone(&Utc))
+        .map_err(|_| format!("expected an RFC 3339 time like 2024-05-01T00:00:00Z, got {s:?}"))
+}
+
+fn parse_hhmm(s: &str) -> Result<NaiveTime, String> {
+    NaiveTime::parse_from_str(s, "%H:%M").map_err(|_| format!("expected HH:MM, got {s:?}"))
+}
+
+/// Whether it is currently within --quiet-start/--quiet-end.
+fn quiet_now(args: &Args) -> bool {
+    let (Some(start), Some(end)) = (args.quiet_start, args.quiet_end) else {
+        return false;
+    };
+    let now = match args.timezone {
+        Timezone::Utc => Utc::now().time(),
+        Timezone::Local => chrono::Local::now().time(),
+        Timezone::Fixed(offset) => Utc::now().with_timezone(&offset).time(),
+    };
+    in_window(now, start, end)
+}
+
+/// Whether `now` is in `[start, end)`, which wraps past midnight when
+/// `start` is later than `end`.
+fn in_window(now: NaiveTime, start: NaiveTime, end: NaiveTime) -> bool {
+    if start <= end {
+        start <= now && now < end
+    } else {
+        now >= start || now < end
+    }
+}
+
+/// Adds `detection` to the repo's queue, dropping the oldest one past `cap`.
+fn enqueue(repo: &str, r: &mut state::RepoState, detection: QueuedDetection, cap: usize) {
+    r.queued.push(detection);
+    if r.queued.len() > cap {
+        let dropped = r.queued.remove(0);
+        warn!(%repo, tag = %dropped.tag, cap, "notification queue full, dropping its oldest entry");
+    } else if r.queued.len() > 1 {
+        info!(%repo, queued = r.queued.len(), "notification queue growing");
+    }
+}
+
+impl From<&Candidate> for QueuedDetection {
+    fn from(c: &Candidate) -> Self {
+        Self {
+            tag: c.tag.clone(),
+            kind: c.kind,
+            previous: c.previous.clone(),
+            title: c.title.clone(),
+            url: c.url.clone(),
+            commit_sha: c.commit_sha.clone(),
+            notes: c.notes.clone(),
+            release_name: c.release_name.clone(),
+            initial: c.initial,
+            prerelease: c.prerelease,
+            promoted_from: c.promoted_from.clone(),
+            verified: c.verified,
+        }
+    }
+}
+
+/// Release asset buttons aren't kept while queued.
+impl From<QueuedDetection> for Candidate {
+    fn from(q: QueuedDetection) -> Self {
+        Self {
+            notes: q.notes,
+            commit_sha: q.commit_sha,
+            title: q.title,
+            url: q.url,
+            previous: q.previous,
+            release_name: q.release_name,
+            initial: q.initial,
+            prerelease: q.prerelease,
+            promoted_from: q.promoted_from,
+            verified: q.verified,
+            ..Candidate::new(q.tag, q.kind)
+        }
+    }
+}
+
+/// Outcome of one pass over all repos.
+struct Pass {
+    failed: usize,
+    /// Set when GitHub rate limited us; the rest of the pass was skipped.
+    rate_limited_until: Option<DateTime<Utc>>,
+}
+
+async fn run_pass(
+    repos: &[RepoConfig],
+    client: &reqwest::Client,
+    octo: &GithubClients,
+    state: &Mutex<Box<dyn StateStore>>,
+    args: &Args,
+    seed: bool,
+) -> Pass {
+    // Once GitHub throttles us the rest of the pass is skipped, not hammered.
+    let rate_limited: Mutex<Option<DateTime<Utc>>> = Mutex::new(None);
+    let ctx = PassContext {
+        seed,
+        digest: args.digest.then(Default::default),
+        quiet: quiet_now(args),
+        muted: ignore_patterns(args),
+        newest: if args.use_graphql { graphql_newest(repos, octo).await } else { HashMap::new() },
+    };
+    let failed = stream::iter(repos)
+        .map(|repo| {
+            let rate_limited = &rate_limited;
+            let ctx = &ctx;
+            async move {
+                if rate_limited.lock().expect("rate limit lock poisoned").is_some() {
+                    debug!(repo = %repo.name, "skipping check while rate limited");
+                    return false;
+                }
+                let notifiers = notifiers_for(repo, args);
+                let octo = octo.for_repo(&repo.name);
+                let res = check_repo(repo, &notifiers, client, octo, state, args, ctx).await;
+                METRICS
+                    .repo_checks
+                    .with_label_values(&[&repo.name, if res.is_ok() { "ok" } else { "error" }])
+                    .inc();
+                if let Err(e) = &res {
+                    error!(repo = %repo.name, error=?e, "repo check failed");
+                    if let Some(reset_at) = github::rate_limited_until(e) {
+                        let mut until = rate_limited.lock().expect("rate limit lock poisoned");
+                        *until = (*until).max(Some(reset_at));
+                        // being throttled says nothing about the repo itself
+                        return false;
+                    }
+                }
+                if let Err(e) = record_outcome(&repo.name, res.as_ref().err(), state, args) {
+                    warn!(repo = %repo.name, error=?e, "recording check outcome failed");
+                }
+                res.is_ok()
+            }
+        })
+        .buffer_unordered(args.concurrency as usize)
+        .filter(|ok| future::ready(!ok))
+        .count()
+        .await;
+    if let Some(digest) = ctx.digest {
+        let entries = digest.into_inner().expect("digest lock poisoned");
+        digest::send(entries, client, state, args).await;
+    }
+    if failed < repos.len() {
+        METRICS.last_successful_poll.set(Utc::now().timestamp());
+    }
+
+    Pass {
+        failed,
+        rate_limited_until: rate_limited.into_inner().expect("rate limit lock poisoned"),
+    }
+}
+
+/// The newest release and tag of every plain GitHub repo among `repos`,
+/// asked for through GraphQL in batches per token. Repos of a failed batch
+/// are left out, so their check goes over REST as without --use-graphql.
+async fn graphql_newest(
+    repos: &[RepoConfig],
+    octo: &GithubClients,
+) -> HashMap<String, github::NewestTags> {
+    let mut batches: HashMap<Option<String>, Vec<&str>> = HashMap::new();
+    let plain = repos.iter().map(|r| r.name.as_str()).filter(|r| on_github(r));
+    for repo in plain.filter(|r| !r.contains(['@', '#'])) {
+        let owner = repo.split('/').next().unwrap_or_default().to_ascii_lowercase();
+        let token = octo.by_owner.contains_key(&owner).then_some(owner);
+        batches.entry(token).or_default().push(repo);
+    }
+    let mut newest = HashMap::new();
+    for (owner, repos) in batches {
+        let octo = owner.as_deref().map_or(&octo.default, |o| octo.for_owner(o));
+        for batch in repos.chunks(github::GRAPHQL_BATCH) {
+            match github::newest_tags(octo, batch).await {
+                Ok(found) => {
+                    debug!(repos = batch.len(), found = found.len(), "GraphQL batch answered");
+                    newest.extend(found.into_iter().map(|(repo, n)| (repo.to_string(), n)));
+                }
+                Err(e) => {
+                    warn!(repos = batch.len(), error=?e, "GraphQL batch failed, using REST for it");
+                }
+            }
+        }
+    }
+    newest
+}
+
+/// Whether what GraphQL reported as `newest` for a repo is already its last
+/// seen tag, the one its REST check would find newest too: the newest (or,
+/// with --latest-pointer, the "Latest") release, else the newest raw tag.
+/// GraphQL orders tags by commit date where REST takes the highest semver,
+/// so raw tags only count when both orders put the same one first.
+fn unchanged_per_graphql(
+    newest: &github::NewestTags,
+    stored: &state::RepoState,
+    latest_pointer: bool,
+) -> bool {
+    let Some(last_seen) = stored.last_seen_tag.as_deref() else {
+        return false;
+    };
+    let release = if latest_pointer { &newest.latest_release } else { &newest.newest_release };
+    match (release, stored.kind) {
+        (Some(tag), Some(Kind::Release)) => tag == last_seen,
+        (None, Some(Kind::Tag)) => {
+            let by_semver = semver_ordered_tags(&newest.tags, String::as_str);
+            newest.tags.first().is_some_and(|t| t == last_seen)
+                && by_semver.first().is_some_and(|t| *t == last_seen)
+        }
+        _ => false,
+    }
+}
+
+/// Tracks consecutive failures and the last error of `repo`, logging when it
+/// enters or leaves backoff.
+fn record_outcome(
+    repo: &str,
+    error: Option<&anyhow::Error>,
+    state: &Mutex<Box<dyn StateStore>>,
+    args: &Args,
+) -> Result<()> {
+    let mut store = state.lock().expect("state lock poisoned");
+    let prev = store.get(repo)?.unwrap_or_default();
+    let before = prev.consecutive_failures;
+    let after = if error.is_none() { 0 } else { before.saturating_add(1) };
+    if after == before && error.is_none() && prev.last_error.is_none() {
+        return Ok(());
+    }
+    if after == args.backoff_after {
+        warn!(%repo, failures = after, "repo keeps failing, backing off");
+    } else if error.is_none() && before >= args.backoff_after {
+        info!(%repo, failures = before, "repo recovered, leaving backoff");
+    }
+    let last_error = error.map(|e| format!("{e:#}"));
+    store.update(repo, &mut |r| {
+        r.consecutive_failures = after;
+        r.last_error_at = last_error.is_some().then(Utc::now);
+        r.last_error = last_error.clone();
+    })
+}
+
+/// Seconds until the next check of a repo polled every `base` seconds that
+/// failed `failures` times in a row: doubles with every failure from `after`
+/// on, capped at `max` (but never below `base`).
+fn backoff_secs(base: u64, failures: u32, after: u32, max: u64) -> u64 {
+    if failures < after {
+        return base;
+    }
+    let doublings = (failures - after + 1).min(32);
+    base.saturating_mul(1 << doublings).min(max.max(base))
+}
+
+/// Builds the watch list from `--config` if given, else from `--repos`.
+fn resolve_repos(args: &Args) -> Result<Vec<RepoConfig>> {
+    let repos = if let Some(path) = &args.config {
+        Config::load(path)?.repos
+    } else {
+        let mut names: Vec<String> = args
+            .repos
+            .as_deref()
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(String::from)
+            .collect();
+        if let Some(path) = &args.repos_file {
+            let list = std::fs::read_to_string(path)
+                .with_context(|| format!("reading repos file {}", path.display()))?;
+            names.extend(parse_repos_file(&list));
+        }
+        let mut seen = HashSet::new();
+        names.retain(|name| seen.insert(name.clone()));
+        names.into_iter().map(RepoConfig::new).collect()
+    };
+
+    let invalid: Vec<&str> = repos
+        .iter()
+        .map(|r| r.name.as_str())
+        .filter(|name| !valid_repo_spec(name))
+        .collect();
+    if !invalid.is_empty() {
+        anyhow::bail!(
+            "invalid repo entries (expected owner/repo, owner/repo@branch, owner/repo#workflow.yml, owner/* or git+<url>): {}",
+            invalid.join(", ")
+        );
+    }
+
+    if args.tg_bot_token.is_none()
+        && (!args.tg_chat_ids.is_empty()
+            || repos.iter().any(|r| {
+                r.chat_id.is_some() || r.stable_chat_id.is_some() || r.prerelease_chat_id.is_some()
+            }))
+    {
+        anyhow::bail!("a Telegram chat id is configured but --tg-bot-token is missing");
+    }
+    let desktop = if cfg!(feature = "desktop") { ", --desktop" } else { "" };
+    for repo in &repos {
+        if notifiers_for(repo, args).is_empty() {
+            anyhow::bail!(
+                "no notification target for {}: set --tg-chat-id, chat_id in the config, --discord-webhook, --slack-webhook, --teams-webhook, --ntfy-topic, --smtp-host, --matrix-homeserver, --stdout{desktop} or --webhook-url",
+                repo.name
+            );
+        }
+    }
+    Ok(repos)
+}
+
+/// The entries of a `--repos-file`: one per line, blank lines and comments
+/// skipped. A comment is a line starting with `#` or the rest of a line after
+/// ` #`, so `owner/repo#workflow.yml` entries still work.
+fn parse_repos_file(list: &str) -> Vec<String> {
+    list.lines()
+        .map(|line| line.split_once(" #").map_or(line, |(entry, _)| entry).trim())
+        .filter(|entry| !entry.is_empty() && !entry.starts_with('#'))
+        .map(String::from)
+        .collect()
+}
+
+/// The patterns of --ignore-file, read afresh every time so edits apply
+/// from the next pass on. A missing or unreadable file mutes nothing.
+fn ignore_patterns(args: &Args) -> Vec<String> {
+    let Some(path) = &args.ignore_file else {
+        return Vec::new();
+    };
+    match std::fs::read_to_string(path) {
+        Ok(list) => parse_repos_file(&list),
+        Err(e) => {
+            warn!(path = %path.display(), error=?e, "reading the ignore file failed, muting nothing");
+            Vec::new()
+        }
+    }
+}
+
+/// Whether the `owner/repo` part of `repo` matches one of `patterns`, in
+/// which `*` stands for any run of characters (case-insensitively, like
+/// GitHub names).
+fn repo_muted(repo: &str, patterns: &[String]) -> bool {
+    let path = repo.split(['@', '#']).next().unwrap_or(repo).to_ascii_lowercase();
+    patterns.iter().any(|p| glob_match(&p.to_ascii_lowercase(), &path))
+}
+
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let Some((head, rest)) = pattern.split_once('*') else {
+        return pattern == text;
+    };
+    let Some(mut text) = text.strip_prefix(head) else {
+        return false;
+    };
+    let mut parts: Vec<&str> = rest.split('*').collect();
+    let tail = parts.pop().unwrap_or_default();
+    for part in parts {
+        match text.find(part) {
+            Some(at) => text = &text[at + part.len()..],
+            None => return false,
+        }
+    }
+    text.len() >= tail.len() && text.ends_with(tail)
+}
+
+/// `owner/repo` (or `owner/*`) with non-empty halves and nothing else,
+/// optionally followed by `@branch` or `#workflow.yml` (not for `owner/*`),
+/// `git+<url>` of any git remote or `docker://<image>`.
+fn valid_repo_spec(name: &str) -> bool {
+    if let Some(url) = git::remote_url(name) {
+        return !url.is_empty() && !url.contains(char::is_whitespace);
+    }
+    if let Some(image) = docker::image(name) {
+        // no `:tag` or `@digest`, every tag of the image is watched
+        let (_, path) = docker::locate(image, docker::DOCKER_HUB);
+        return !image.is_empty()
+            && !image.contains(char::is_whitespace)
+            && !path.contains([':', '@', '*'])
+            && !path.ends_with('/');
+    }
+    let name = match name.split_once(['@', '#']) {
+        Some((path, rest)) if !rest.is_empty() && !path.ends_with("/*") => path,
+        Some(_) => return false,
+        None => name,
+    };
+    match name.split_once('/') {
+        Some((owner, repo)) => {
+            let part_ok = |p: &str| !p.is_empty() && !p.contains(['/', ' ', '\t']);
+            part_ok(owner) && part_ok(repo)
+        }
+        None => false,
+    }
+}
+
+fn repo_names(repos: &[RepoConfig]) -> Vec<String> {
+    repos.iter().map(|r| r.name.clone()).collect()
+}
+
+/// Forgets every repo in the state that isn't among `repos`, the watched
+/// list after wildcards and renames were resolved.
+fn prune_orphans(store: &mut dyn StateStore, repos: &[RepoConfig]) -> Result<()> {
+    let watched: HashSet<&str> = repos.iter().map(|r| r.name.as_str()).collect();
+    let mut pruned = 0;
+    for (repo, r) in store.list()? {
+        if watched.contains(repo.as_str()) {
+            continue;
+        }
+        info!(%repo, last_seen = ?r.last_seen_tag, "pruning state of a repo no longer watched");
+        store.remove(&repo)?;
+        pruned += 1;
+    }
+    if pruned > 0 {
+        store.flush()?;
+    }
+    Ok(())
+}
+
+/// Replaces `owner/*` entries with every repo of that owner, inheriting the
+/// entry's settings. A repo listed explicitly keeps its own entry.
+async fn expand_repos(
+    configured: &[RepoConfig],
+    octo: &GithubClients,
+    state: &Mutex<Box<dyn StateStore>>,
+    args: &Args,
+) -> Result<Vec<RepoConfig>> {
+    let mut seen = HashSet::new();
+    let mut renamed = HashMap::new();
+    let mut repos = Vec::new();
+    for entry in configured.iter().filter(|r| r.wildcard_owner().is_none()) {
+        let mut entry = entry.clone();
+        if args.follow_renames && on_github(&entry.name) {
+            let octo = octo.for_repo(&entry.name);
+            entry.name = follow_rename(&entry.name, octo, state, &mut renamed).await?;
+        }
+        if seen.insert(entry.name.clone()) {
+            repos.push(entry);
+        }
+    }
+    for entry in configured {
+        let Some(owner) = entry.wildcard_owner() else {
+            continue;
+        };
+        let listed = github::list_owner_repos(octo.for_owner(owner), owner)
+            .await
+            .with_context(|| format!("listing repos for {}", entry.name))?;
+        let before = repos.len();
+        for listed in listed {
+            if args.skip_archived && (listed.archived || listed.disabled) {
+                debug!(repo = %listed.full_name, "skipping archived repo");
+                continue;
+            }
+            if seen.insert(listed.full_name.clone()) {
+                repos.push(RepoConfig {
+                    name: listed.full_name,
+                    ..entry.clone()
+                });
+            }
+        }
+        info!(pattern = %entry.name, count = repos.len() - before, "expanded wildcard");
+    }
+    Ok(repos)
+}
+
+/// The GitHub client, authenticated as the GitHub App installation when one
+/// is configured and with the personal token otherwise.
+async fn github_client(args: &Args, client: &reqwest::Client) -> Result<octocrab::Octocrab> {
+    let mut auth = github::Auth::None;
+    let app = args.gh_app_id.zip(args.gh_app_private_key_path.as_ref());
+    if let (Some((app_id, key_path)), Some(installation)) = (app, args.gh_app_installation_id) {
+        let pem = std::fs::read(key_path)
+            .with_context(|| format!("reading GitHub App key {}", key_path.display()))?;
+        let key = jsonwebtoken::EncodingKey::from_rsa_pem(&pem)
+            .with_context(|| format!("parsing GitHub App key {}", key_path.display()))?;
+        let app = octocrab::auth::AppAuth {
+            app_id: app_id.into(),
+            key,
+        };
+        auth = github::Auth::App(app, installation);
+    } else if let Some(token) = &args.github_token {
+        auth = github::Auth::Token(token.clone());
+    }
+
+    // octocrab's own hyper client has neither proxy support nor timeouts,
+    // so GitHub requests go through the shared reqwest client as well
+    let base_url = args.github_base_url.as_deref();
+    let octo = github::octocrab_over(client.clone(), base_url, auth, args.gh_max_retries)
+        .with_context(|| format!("invalid --github-base-url {:?}", args.github_base_url))?;
+
+    let Some(installation_id) = args.gh_app_installation_id else {
+        if args.github_token.is_some() {
+            check_token(&octo, "--github-token").await?;
+        } else if args.gh_app_id.is_none() {
+            info!("no --github-token, GitHub is accessed anonymously (60 requests an hour)");
+        }
+        return Ok(octo);
+    };
+    // Minting the first token up front turns a bad app id, key or installation
+    // into a startup error. Installation tokens last an hour; octocrab re-mints
+    // one before any request made within 30s of its expiry.
+    let (octo, _) = octo
+        .installation_and_token(installation_id.into())
+        .await
+        .context("getting a GitHub App installation token")?;
+    info!(app_id = ?args.gh_app_id, installation_id, "authenticated as GitHub App");
+    Ok(octo)
+}
+
+/// Logs who GitHub requests with the token given as `flag` are made as, so a
+/// 404 for a private repo can be told apart from a bad token; a token GitHub
+/// rejects fails right away.
+async fn check_token(octo: &octocrab::Octocrab, flag: &str) -> Result<()> {
+    match github::token_login(octo).await {
+        Ok(login) => info!(%login, "authenticated to GitHub with {flag}"),
+        Err(github::GithubError::Status {
+            status: http::StatusCode::UNAUTHORIZED,
+            ..
+        }) => anyhow::bail!("GitHub rejected {flag} (401): it is invalid, expired or revoked"),
+        // e.g. GitHub unreachable for now, the first pass will tell
+        Err(e) => warn!(error=?e, "checking {flag} failed"),
+    }
+    Ok(())
+}
+
+/// The GitHub clients to use: one per owner with an --owner-token, the
+/// default credentials for every other repo.
+struct GithubClients {
+    default: octocrab::Octocrab,
+    /// Keyed by lowercased owner
+    by_owner: HashMap<String, octocrab::Octocrab>,
+}
+
+impl GithubClients {
+    /// The client for `repo`, an entry like owner/repo@branch.
+    fn for_repo(&self, repo: &str) -> &octocrab::Octocrab {
+        self.for_owner(repo.split('/').next().unwrap_or_default())
+    }
+
+    fn for_owner(&self, owner: &str) -> &octocrab::Octocrab {
+        self.by_owner.get(&owner.to_ascii_lowercase()).unwrap_or(&self.default)
+    }
+}
+
+async fn github_clients(args: &Args, client: &reqwest::Client) -> Result<GithubClients> {
+    let default = github_client(args, client).await?;
+    let mut by_owner = HashMap::new();
+    for (owner, token) in &args.owner_tokens {
+        let auth = github::Auth::Token(token.clone());
+        let base_url = args.github_base_url.as_deref();
+        let octo = github::octocrab_over(client.clone(), base_url, auth, args.gh_max_retries)
+            .with_context(|| format!("invalid --github-base-url {:?}", args.github_base_url))?;
+        check_token(&octo, &format!("the --owner-token of {owner}")).await?;
+        by_owner.insert(owner.clone(), octo);
+    }
+    Ok(GithubClients { default, by_owner })
+}
+
+/// `builder` sending through --proxy, if given; otherwise reqwest falls
+/// back to the HTTPS_PROXY / ALL_PROXY environment variables.
+fn proxied(builder: reqwest::ClientBuilder, args: &Args) -> Result<reqwest::ClientBuilder> {
+    Ok(match &args.proxy {
+        Some(url) => builder.proxy(reqwest::Proxy::all(url).context("invalid --proxy")?),
+        None => builder,
+    })
+}
+
+/// `name` (an entry like owner/repo@branch) under the repo's current name,
+/// moving its state over when GitHub reports that the repo was renamed.
+/// `renamed` caches lookups so each repo is only asked about once per refresh.
+async fn follow_rename(
+    name: &str,
+    octo: &octocrab::Octocrab,
+    state: &Mutex<Box<dyn StateStore>>,
+    renamed: &mut HashMap<String, String>,
+) -> Result<String> {
+    let split = name.find(['@', '#']).unwrap_or(name.len());
+    let (path, suffix) = name.split_at(split);
+    let current = match renamed.get(path) {
+        Some(current) => current.clone(),
+        None => {
+            let current = match github::current_name(octo, path).await {
+                Ok(current) => current,
+                Err(e @ github::GithubError::RateLimited { .. }) => return Err(e.into()),
+                Err(e) => {
+                    // the check itself will keep reporting what's wrong
+                    warn!(repo = %path, error=?e, "looking up the repo's current name failed");
+                    path.to_string()
+                }
+            };
+            renamed.insert(path.to_string(), current.clone());
+            current
+        }
+    };
+    // GitHub names are case-insensitive, so a differently-cased entry isn't a rename
+    if current.eq_ignore_ascii_case(path) {
+        return Ok(name.to_string());
+    }
+    let new_name = format!("{current}{suffix}");
+    warn!(repo = %name, new_name = %new_name, "repo was renamed, following it; update the repo list");
+    if state.lock().expect("state lock poisoned").rename(name, &new_name)? {
+        info!(repo = %name, new_name = %new_name, "moved state to the new name");
+    }
+    Ok(new_name)
+}
+
+/// Fails unless Telegram accepts the bot token and the bot can access every
+/// configured chat (skipped with --skip-startup-check).
+async fn startup_check(repos: &[RepoConfig], client: &reqwest::Client, args: &Args) -> Result<()> {
+    if args.skip_startup_check {
+        return Ok(());
+    }
+    let mut chats = Vec::new();
+    let mut config = None;
+    let entries = repos.iter().flat_map(RepoConfig::with_channels);
+    for notifier in entries.flat_map(|r| notifiers_for(&r, args)) {
+        if let Notifier::Telegram { config: c, chat_id, .. } = notifier {
+            if !chats.contains(&chat_id) {
+                chats.push(chat_id);
+            }
+            config = Some(c);
+        }
+    }
+    let Some(config) = config else {
+        return Ok(());
+    };
+    notify::check_telegram(client, &config, &chats).await.context(Exit::StartupCheck)?;
+    info!(chats = chats.len(), "Telegram bot token and chats verified");
+    Ok(())
+}
+
+/// The targets for `candidate`: the repo's stable or pre-release channel
+/// when one is configured for it, else `default`.
+fn route<'a>(
+    entry: &RepoConfig,
+    candidate: &Candidate,
+    default: &'a [Notifier],
+    args: &Args,
+) -> Cow<'a, [Notifier]> {
+    match entry.routed(candidate.is_prerelease()) {
+        Some(routed) => Cow::Owned(notifiers_for(&routed, args)),
+        None => Cow::Borrowed(default),
+    }
+}
+
+/// All targets a detection for `repo` should be sent to.
+fn notifiers_for(repo: &RepoConfig, args: &Args) -> Vec<Notifier> {
+    let mut notifiers = Vec::new();
+    if let Some(bot_token) = &args.tg_bot_token {
+        let config = TelegramConfig {
+            bot_token: bot_token.clone(),
+            parse_mode: args.tg_parse_mode,
+            max_retries: args.tg_max_retries,
+            no_link_preview: args.no_link_preview,
+            silent: args.tg_silent,
+        };
+        for &chat_id in repo.chat_ids(&args.tg_chat_ids) {
+            notifiers.push(Notifier::Telegram {
+                config: config.clone(),
+                chat_id,
+                thread_id: repo.thread_id.or(args.tg_thread_id),
+            });
+        }
+    }
+    if let Some(webhook_url) = &args.discord_webhook {
+        notifiers.push(Notifier::Discord {
+            webhook_url: webhook_url.clone(),
+        });
+    }
+    if let Some(webhook_url) = &args.slack_webhook {
+        notifiers.push(Notifier::Slack {
+            webhook_url: webhook_url.clone(),
+        });
+    }
+    if let Some(webhook_url) = &args.teams_webhook {
+        notifiers.push(Notifier::Teams {
+            webhook_url: webhook_url.clone(),
+        });
+    }
+    if let Some(topic) = &args.ntfy_topic {
+        notifiers.push(Notifier::Ntfy {
+            server: args.ntfy_server.clone(),
+            topic: topic.clone(),
+        });
+    }
+    if let (Some(host), Some(from)) = (&args.smtp_host, &args.email_from) {
+        notifiers.push(Notifier::Email {
+            config: EmailConfig {
+                host: host.clone(),
+                port: args.smtp_port,
+                user: args.smtp_user.clone(),
+                pass: args.smtp_pass.clone(),
+                from: from.clone(),
+                to: args.email_to.clone(),
+            },
+        });
+    }
+    if let (Some(homeserver), Some(token), Some(room)) =
+        (&args.matrix_homeserver, &args.matrix_token, &args.matrix_room)
+    {
+        notifiers.push(Notifier::Matrix {
+            config: MatrixConfig {
+                homeserver: homeserver.clone(),
+                token: token.clone(),
+                room: room.clone(),
+            },
+        });
+    }
+    if args.stdout {
+        notifiers.push(Notifier::Stdout);
+    }
+    #[cfg(feature = "desktop")]
+    if args.desktop {
+        notifiers.push(Notifier::Desktop);
+    }
+    if let Some(url) = &args.webhook_url {
+        notifiers.push(Notifier::Webhook {
+            url: url.clone(),
+            timeout: Duration::from_secs(args.webhook_timeout_secs),
+        });
+    }
+    notifiers
+}
+
+// the span puts the repo on every line logged during the check, octocrab's
+// and the notifiers' included, so interleaved concurrent checks stay apart
+#[instrument(skip_all, fields(repo = %entry.name))]
+async fn check_repo(
+    entry: &RepoConfig,
+    notifiers: &[Notifier],
+    client: &reqwest::Client,
+    octo: &octocrab::Octocrab,
+    state: &Mutex<Box<dyn StateStore>>,
+    args: &Args,
+    ctx: &PassContext,
+) -> Result<()> {
+    let repo = entry.name.as_str();
+    let muted = repo_muted(repo, &ctx.muted);
+    let fetched = if let Some(url) = git::remote_url(repo) {
+        let timeout = Duration::from_secs(args.http_timeout_secs);
+        let tags = git::remote_tags(url, timeout).await?;
+        Fetched::Modified {
+            value: (Kind::Tag, listed_tags(git::web_url(url), &tags, |(name, _)| name)),
+            etag: None,
+        }
+    } else if let Some(image) = docker::image(repo) {
+        let (registry, name) = docker::locate(image, &args.docker_registry);
+        let auth = args.docker_user.as_deref().zip(args.docker_pass.as_deref());
+        let tags = docker::tags(client, registry, &name, auth).await?;
+        let page = docker::web_url(registry, &name);
+        Fetched::Modified {
+            value: (Kind::Tag, listed_tags(&page, &tags, String::as_str)),
+            etag: None,
+        }
+    } else if repo.contains('#') {
+        return check_workflow(repo, notifiers, client, octo, state, args, muted).await;
+    } else {
+        if let Some(newest) = ctx.newest.get(repo) {
+            let mut store = state.lock().expect("state lock poisoned");
+            let stored = store.get(repo)?.unwrap_or_default();
+            if unchanged_per_graphql(newest, &stored, entry.latest_pointer(args.latest_pointer)) {
+                debug!(%repo, "unchanged according to GraphQL");
+                return store.update(repo, &mut |r| r.last_checked_at = Some(Utc::now()));
+            }
+        }
+        github_tags(entry, octo, state, args).await?
+    };
+    state
+        .lock()
+        .expect("state lock poisoned")
+        .update(repo, &mut |r| r.last_checked_at = Some(Utc::now()))?;
+    let (etag_kind, tags, etag) = match fetched {
+        Fetched::NotModified => {
+            debug!(%repo, "not modified");
+            return Ok(());
+        }
+        Fetched::Modified {
+            value: (key, tags),
+            etag,
+        } => (key, tags, etag),
+    };
+    let Some(latest) = tags.first() else {
+        debug!(%repo, "no releases left after --stable-only/--prerelease-only filtering");
+        return Ok(());
+    };
+
+    let stored = state.lock().expect("state lock poisoned").get(repo)?.unwrap_or_default();
+    let (last_seen, mut prereleases) = (stored.last_seen_tag, stored.prereleases);
+    let mut last_notified_at = stored.last_notified_at;
+    let missing = last_seen.as_deref().filter(|t| !tags.iter().any(|c| &c.tag == t));
+    if let Some(removed) = missing.filter(|_| args.notify_on_retraction) {
+        if tag_removed(repo, removed, etag_kind, octo).await? {
+            warn!(%repo, tag = %removed, "previously seen tag was removed");
+            if !muted {
+                notify_retraction(repo, removed, notifiers, client, args).await;
+            }
+            // a newer tag than the removed one is announced as usual, anything
+            // else is already known and only becomes the last seen tag
+            if parse_version(&latest.tag) <= parse_version(removed) {
+                let mut store = state.lock().expect("state lock poisoned");
+                store.update(repo, &mut |r| {
+                    r.last_seen_tag = Some(latest.tag.clone());
+                    r.kind = Some(latest.kind);
+                })?;
+                if let Some(etag) = &etag {
+                    store.set_etag(repo, etag_kind, etag)?;
+                }
+                return Ok(());
+            }
+        }
+    }
+    let pending: Vec<&Candidate> = match last_seen {
+        None if ctx.seed || args.seed_new_repos => {
+            info!(%repo, tag = %latest.tag, "seeded without notifying");
+            state.lock().expect("state lock poisoned").update(repo, &mut |r| {
+                r.last_seen_tag = Some(latest.tag.clone());
+                r.kind = Some(latest.kind);
+                if latest.is_prerelease() {
+                    remember_prerelease(&mut r.prereleases, &latest.tag);
+                }
+            })?;
+            Vec::new()
+        }
+        // no change
+        Some(ref t) if t == &latest.tag => Vec::new(),
+        Some(ref t) => match tags.iter().position(|c| &c.tag == t) {
+            // everything before last_seen is newer; announce oldest-first
+            Some(pos) => {
+                let cap = args.max_catchup as usize;
+                if pos > cap {
+                    info!(%repo, skipped = pos - cap, "too many missed tags, announcing newest only");
+                }
+                tags[..pos.min(cap)].iter().rev().collect()
+            }
+            // last_seen scrolled off the page, just announce the newest
+            None => vec![latest],
+        },
+        // --since already narrowed releases down to the ones worth reporting
+        None if args.since.is_some() && latest.kind == Kind::Release => {
+            tags.iter().take(args.max_catchup as usize).rev().collect()
+        }
+        None => vec![latest],
+    };
+
+    let mut previous = last_seen.clone();
+    let known_prereleases = prereleases.clone();
+    let mut pending: Vec<Candidate> = pending
+        .into_iter()
+        .map(|candidate| {
+            let promoted_from = candidate.promotes(&prereleases);
+            if candidate.is_prerelease() {
+                remember_prerelease(&mut prereleases, &candidate.tag);
+            }
+            Candidate {
+                previous: previous.replace(candidate.tag.clone()),
+                initial: last_seen.is_none(),
+                promoted_from,
+                ..candidate.clone()
+            }
+        })
+        .collect();
+    // remembered up front: a pre-release that fails to send is retried anyway
+    if prereleases != known_prereleases {
+        state
+            .lock()
+            .expect("state lock poisoned")
+            .update(repo, &mut |r| r.prereleases = prereleases.clone())?;
+    }
+    // filtered tags still advance last_seen so they aren't re-evaluated
+    let filtered = |candidate: &Candidate| match candidate.kind {
+        _ if muted => Some("repo muted by --ignore-file"),
+        Kind::Commit => None,
+        _ => tag_filtered_out(&candidate.tag, entry, args)
+            .or_else(|| author_filtered_out(candidate, entry)),
+    };
+
+    let digest = ctx.digest.as_ref().filter(|_| !ctx.quiet);
+    if let (Some(digest), Some(newest)) = (digest, pending.last()) {
+        let announce: Vec<Candidate> =
+            pending.iter().filter(|c| filtered(c).is_none()).cloned().collect();
+        if !announce.is_empty() {
+            for candidate in &announce {
+                let (tag, change) = (&candidate.tag, Change::of(candidate));
+                info!(%repo, %tag, %change, "new tag detected, adding it to the digest");
+            }
+            // one entry per channel when stable and pre-releases go apart
+            let groups = if entry.stable_chat_id.is_none() && entry.prerelease_chat_id.is_none() {
+                vec![announce]
+            } else {
+                let (pre, stable): (Vec<_>, Vec<_>) =
+                    announce.into_iter().partition(Candidate::is_prerelease);
+                vec![stable, pre].into_iter().filter(|g| !g.is_empty()).collect()
+            };
+            // the state moves on once the digest went out
+            let mut digest = digest.lock().expect("digest lock poisoned");
+            for candidates in groups {
+                digest.push(DigestEntry {
+                    repo: repo.to_string(),
+                    notifiers: route(entry, &candidates[0], notifiers, args).into_owned(),
+                    candidates,
+                    newest: (newest.tag.clone(), newest.kind),
+                    etag: etag.clone().map(|etag| (etag_kind, etag)),
+                });
+            }
+            return Ok(());
+        }
+    }
+
+    // once a delivery failed, later detections queue up behind it to keep their order
+    let mut undelivered = false;
+    for candidate in &mut pending {
+        let reason = filtered(candidate);
+        if reason.is_none() && args.show_verification {
+            candidate.verified = tag_verification(repo, candidate, octo).await?;
+        }
+        let candidate = &*candidate;
+        let tag = &candidate.tag;
+        let (mut queued, mut held) = (None, None);
+        let cooldown = args.notify_cooldown_secs.filter(|&secs| {
+            in_cooldown(last_notified_at, secs, Utc::now())
+        });
+        let notified = if let Some(reason) = reason {
+            debug!(%repo, %tag, reason, "tag filtered out");
+            false
+        } else if ctx.quiet || undelivered {
+            let change = Change::of(candidate);
+            let reason = if ctx.quiet { "during quiet hours" } else { "behind a failed one" };
+            info!(%repo, %tag, %change, "new tag detected {reason}, queued");
+            queued = Some(QueuedDetection::from(candidate));
+            false
+        } else if let Some(secs) = cooldown {
+            info!(%repo, %tag, cooldown_secs = secs, "new tag detected during the cooldown, held");
+            held = Some(QueuedDetection::from(candidate));
+            false
+        } else {
+            info!(%repo, %tag, change = %Change::of(candidate), "new tag detected");
+            let notifiers = route(entry, candidate, notifiers, args);
+            match notify_all(repo, &notifiers, candidate, client, args).await {
+                Ok(()) => {
+                    last_notified_at = Some(Utc::now());
+                    true
+                }
+                Err(e) => {
+                    warn!(%repo, %tag, error=?e, "delivery failed, queued to retry on later passes");
+                    undelivered = true;
+                    queued = Some(QueuedDetection::from(candidate));
+                    false
+                }
+            }
+        };
+        state.lock().expect("state lock poisoned").update(repo, &mut |r| {
+            r.last_seen_tag = Some(tag.clone());
+            r.kind = Some(candidate.kind);
+            if notified {
+                r.last_notified_at = Some(Utc::now());
+                // superseded by the newer tag just announced
+                r.held = None;
+            }
+            if let Some(detection) = &held {
+                // the compare link spans every tag held back since the last message
+                let previous = match &r.held {
+                    Some(earlier) => earlier.previous.clone(),
+                    None => detection.previous.clone(),
+                };
+                r.held = Some(QueuedDetection {
+                    previous,
+                    ..detection.clone()
+                });
+            }
+            if let Some(detection) = &queued {
+                enqueue(repo, r, detection.clone(), args.max_queued as usize);
+            }
+        })?;
+    }
+
+    // Only remember the ETag once everything was delivered, otherwise a 304
+    // next poll would hide the detections we failed to send.
+    if let Some(etag) = etag {
+        state.lock().expect("state lock poisoned").set_etag(repo, etag_kind, &etag)?;
+    }
+    Ok(())
+}
+
+/// Whether `candidate`'s tag is signed and verified by GitHub, for
+/// --show-verification. Only releases and raw tags of GitHub repos can be
+/// told; failing to check leaves the message without a mark.
+async fn tag_verification(
+    repo: &str,
+    candidate: &Candidate,
+    octo: &octocrab::Octocrab,
+) -> Result<Option<bool>> {
+    if !on_github(repo) || !matches!(candidate.kind, Kind::Release | Kind::Tag) {
+        return Ok(None);
+    }
+    match github::tag_verified(octo, repo, &candidate.tag).await {
+        Ok(verified) => Ok(Some(verified)),
+        Err(e @ github::GithubError::RateLimited { .. }) => Err(e.into()),
+        Err(e) => {
+            warn!(%repo, tag = %candidate.tag, error=?e, "checking the tag's signature failed");
+            Ok(None)
+        }
+    }
+}
+
+/// Whether `tag`, no longer in the fetched list, was really deleted rather
+/// than filtered out or pushed off the page. The lists of git remotes and
+/// images are complete; branch commits and workflow runs can't be retracted.
+async fn tag_removed(repo: &str, tag: &str, kind: Kind, octo: &octocrab::Octocrab) -> Result<bool> {
+    if !on_github(repo) {
+        return Ok(true);
+    }
+    let release = match kind {
+        Kind::Release => true,
+        Kind::Tag => false,
+        Kind::Commit | Kind::Workflow => return Ok(false),
+    };
+    Ok(!github::tag_exists(octo, repo, tag, release).await?)
+}
+
+/// Tells every notifier that `tag` was removed from `repo`. Failures are
+/// only logged, the state rolls back either way.
+async fn notify_retraction(
+    repo: &str,
+    tag: &str,
+    notifiers: &[Notifier],
+    client: &reqwest::Client,
+    args: &Args,
+) {
+    for notifier in notifiers {
+        let text = retraction_message(repo, tag, args, notifier);
+        match notifier.send(client, text, &[], args.dry_run).await {
+            Ok(()) => METRICS.notifications_sent.with_label_values(&[notifier.kind()]).inc(),
+            Err(e) => error!(%repo, %tag, target = %notifier, error=?e, "retraction notice failed"),
+        }
+    }
+}
+
+/// "⚠️ Tag {tag} in {repo} was removed", formatted for `notifier` like
+/// [`Notifier::format_message`] does for detections.
+fn retraction_message(repo: &str, tag: &str, args: &Args, notifier: &Notifier) -> String {
+    let url = format!("{}/{repo}/tags", github_web_url(args));
+    let url = match (git::remote_url(repo), docker::image(repo)) {
+        (Some(remote), _) => git::web_url(remote).to_string(),
+        (_, Some(image)) => {
+            let (registry, name) = docker::locate(image, &args.docker_registry);
+            docker::web_url(registry, &name)
+        }
+        _ => url,
+    };
+    let icon = if args.no_emoji { "" } else { "\u{26A0}\u{FE0F} " };
+    let escape = escape_for(notifier);
+    match notifier {
+        Notifier::Webhook { .. } => serde_json::json!({
+            "repo": repo,
+            "tag": tag,
+            "url": url,
+            "removed": true,
+            "detected_at": Utc::now(),
+        })
+        .to_string(),
+        Notifier::Teams { .. } => serde_json::json!({
+            "@type": "MessageCard",
+            "@context": "https://schema.org/extensions",
+            "summary": format!("Tag removed in {repo}"),
+            "title": format!("Tag removed in {repo}"),
+            "text": format!("{tag} was removed"),
+        })
+        .to_string(),
+        _ if notifier.takes_ntfy_message() => {
+            let message = NtfyMessage {
+                title: format!("Tag removed in {repo}"),
+                message: format!("{icon}Tag {tag} in {repo} was removed"),
+                click: url,
+            };
+            serde_json::to_string(&message).expect("ntfy message serializes")
+        }
+        Notifier::Stdout => format!("{repo} {tag} removed"),
+        Notifier::Email { .. } => {
+            format!("Tag removed: {repo} {tag}\n{icon}Tag {tag} in {repo} was removed\n{url}")
+        }
+        _ => format!("{icon}Tag {} in {} was removed\n{}", escape(tag), escape(repo), escape(&url)),
+    }
+}
+
+/// Fetches what is new on GitHub for `repo`, newest first, with the kind of
+/// detection and the ETag the endpoint answered with.
+async fn github_tags(
+    entry: &RepoConfig,
+    octo: &octocrab::Octocrab,
+    state: &Mutex<Box<dyn StateStore>>,
+    args: &Args,
+) -> Result<Fetched<(Kind, Vec<Candidate>)>> {
+    let repo = entry.name.as_str();
+    // `owner/repo@branch` entries watch the branch's commits instead of tags
+    let (path, branch) = match repo.split_once('@') {
+        Some((path, branch)) => (path, Some(branch)),
+        None => (repo, None),
+    };
+    let (owner, name) = path
+        .split_once('/')
+        .context("repo must be owner/repo")?;
+
+    let etag = |kind| state.lock().expect("state lock poisoned").etag(repo, kind);
+
+    // Strategy: prefer releases (if any), else raw tags. Both are newest-first,
+    // like branch commits. A 304 means nothing changed since the ETag was stored.
+    if let Some(branch) = branch {
+        return Ok(branch_commits(octo, owner, name, branch, etag(Kind::Commit)?, page_size(args))
+            .await?
+            .map(|value| (Kind::Commit, value)));
+    }
+    let releases = if entry.latest_pointer(args.latest_pointer) {
+        latest_release(octo, owner, name, etag(Kind::Release)?, args).await
+    } else {
+        release_tags(octo, owner, name, etag(Kind::Release)?, args).await
+    };
+    match releases {
+        Ok(fetched) => Ok(fetched.map(|value| (Kind::Release, value))),
+        Err(e) if github::rate_limited_until(&e).is_some() => Err(e),
+        Err(_) => Ok(raw_tags(octo, owner, name, etag(Kind::Tag)?, page_size(args))
+            .await?
+            .map(|value| (Kind::Tag, value))),
+    }
+}
+
+/// Whether `repo` is watched through the GitHub API, rather than being a
+/// git remote or container image.
+fn on_github(repo: &str) -> bool {
+    git::remote_url(repo).is_none() && docker::image(repo).is_none()
+}
+
+/// Candidates for the tags of a `git+<url>` remote or `docker://` image,
+/// highest version first. They link to `page`, there is no release page or
+/// (GitHub) commit.
+fn listed_tags<T>(page: &str, tags: &[T], name: impl Fn(&T) -> &str) -> Vec<Candidate> {
+    semver_ordered_tags(tags, &name)
+        .into_iter()
+        .map(|tag| Candidate {
+            url: Some(page.to_string()),
+            ..Candidate::new(name(tag), Kind::Tag)
+        })
+        .collect()
+}
+
+/// Handles `owner/repo#workflow.yml` entries: announces the newest completed
+/// run of the workflow when its conclusion differs from the previous run's.
+/// The first run seen is only recorded, since there is nothing to compare to.
+async fn check_workflow(
+    repo: &str,
+    notifiers: &[Notifier],
+    client: &reqwest::Client,
+    octo: &octocrab::Octocrab,
+    state: &Mutex<Box<dyn StateStore>>,
+    args: &Args,
+    muted: bool,
+) -> Result<()> {
+    #[derive(Deserialize)]
+    struct Runs {
+        workflow_runs: Vec<models::workflows::Run>,
+    }
+
+    let (path, workflow) = repo
+        .split_once('#')
+        .context("repo must be owner/repo#workflow")?;
+    let (owner, name) = path
+        .split_once('/')
+        .context("repo must be owner/repo")?;
+    let etag = state.lock().expect("state lock poisoned").etag(repo, Kind::Workflow)?;
+    let route =
+        format!("/repos/{owner}/{name}/actions/workflows/{workflow}/runs?status=completed&per_page=1");
+    let fetched: Fetched<Runs> = github::get_json(octo, &route, etag.as_deref()).await?;
+    state
+        .lock()
+        .expect("state lock poisoned")
+        .update(repo, &mut |r| r.last_checked_at = Some(Utc::now()))?;
+    let (runs, etag) = match fetched {
+        Fetched::NotModified => {
+            debug!(%repo, "not modified");
+            return Ok(());
+        }
+        Fetched::Modified { value, etag } => (value.workflow_runs, etag),
+    };
+
+    if let Some(run) = runs.into_iter().next() {
+        let run_id = run.id.to_string();
+        let conclusion = run.conclusion.unwrap_or_else(|| "unknown".into());
+        let previous = state.lock().expect("state lock poisoned").get(repo)?.unwrap_or_default();
+        if previous.last_seen_tag.as_deref() != Some(run_id.as_str()) {
+            let changed = previous.conclusion.as_deref().is_some_and(|c| c != conclusion);
+            if changed && muted {
+                debug!(%repo, run = %run_id, %conclusion, "workflow result changed, repo is muted");
+            } else if changed {
+                info!(%repo, run = %run_id, %conclusion, "workflow result changed");
+                let candidate = Candidate {
+                    commit_sha: Some(run.head_sha),
+                    title: Some(conclusion.clone()),
+                    url: Some(run.html_url.to_string()),
+                    ..Candidate::new(run_id.clone(), Kind::Workflow)
+                };
+                notify_all(repo, notifiers, &candidate, client, args).await?;
+            }
+            state.lock().expect("state lock poisoned").update(repo, &mut |r| {
+                r.last_seen_tag = Some(run_id.clone());
+                r.conclusion = Some(conclusion.clone());
+                r.kind = Some(Kind::Workflow);
+                if changed {
+                    r.last_notified_at = Some(Utc::now());
+                }
+            })?;
+        }
+    } else {
+        debug!(%repo, "no completed workflow runs yet");
+    }
+
+    if let Some(etag) = etag {
+        state.lock().expect("state lock poisoned").set_etag(repo, Kind::Workflow, &etag)?;
+    }
+    Ok(())
+}
+
+/// Why `tag` must not be announced, if it fails the include/exclude patterns
+/// or the repo's `min_version`. Empty patterns are treated as absent.
+fn tag_filtered_out(tag: &str, entry: &RepoConfig, args: &Args) -> Option<&'static str> {
+    fn set(r: &Option<Regex>) -> Option<&Regex> {
+        r.as_ref().filter(|r| !r.as_str().is_empty())
+    }
+    if let Some(include) = set(&args.tag_include) {
+        if !include.is_match(tag) {
+            return Some("does not match --tag-include-regex");
+        }
+    }
+    if let Some(exclude) = set(&args.tag_exclude) {
+        if exclude.is_match(tag) {
+            return Some("matches --tag-exclude-regex");
+        }
+    }
+    if let Some(min) = &entry.min_version {
+        match parse_version(tag) {
+            Some(v) if v < *min => return Some("below min_version"),
+            Some(_) => {}
+            None => debug!(repo = %entry.name, %tag, "not semver, min_version doesn't apply"),
+        }
+    }
+    None
+}
+
+/// Why a release is skipped because of the entry's `allowed_authors`;
+/// detections without an author (raw tags) aren't filtered.
+fn author_filtered_out(candidate: &Candidate, entry: &RepoConfig) -> Option<&'static str> {
+    let allowed = entry.allowed_authors.as_ref()?;
+    let Some(author) = &candidate.author else {
+        debug!(repo = %entry.name, tag = %candidate.tag, "no author, allowed_authors doesn't apply");
+        return None;
+    };
+    let allowed = allowed.iter().any(|a| a.eq_ignore_ascii_case(author));
+    (!allowed).then_some("author not in allowed_authors")
+}
+
+/// Keeps the keyboard of a release with many assets manageable.
+const MAX_ASSET_BUTTONS: usize = 8;
+
+/// What every target was sent last, for `--dedupe-window-secs`.
+static RECENTLY_SENT: LazyLock<SentCache> = LazyLock::new(SentCache::default);
+
+/// The most entries [`SentCache`] keeps; the oldest go first.
+const SENT_CACHE_CAPACITY: usize = 10_000;
+
+/// `(repo, tag, target)` keys with the time they were sent.
+#[derive(Default)]
+struct SentCache {
+    sent: Mutex<HashMap<(String, String, String), Instant>>,
+}
+
+impl SentCache {
+    fn contains(&self, key: &(String, String, String), window: Duration) -> bool {
+        let sent = self.sent.lock().expect("sent cache lock poisoned");
+        sent.get(key).is_some_and(|at| at.elapsed() < window)
+    }
+
+    fn insert(&self, key: (String, String, String), window: Duration) {
+        if window.is_zero() {
+            return;
+        }
+        let mut sent = self.sent.lock().expect("sent cache lock poisoned");
+        sent.retain(|_, at| at.elapsed() < window);
+        if sent.len() >= SENT_CACHE_CAPACITY {
+            let oldest = sent.iter().min_by_key(|(_, at)| **at).map(|(k, _)| k.clone());
+            sent.remove(&oldest.expect("cache is full"));
+        }
+        sent.insert(key, Instant::now());
+    }
+}
+
+/// Sends the detection to every notifier. Succeeds if at least one target got it,
+/// so one broken target doesn't block delivery (and state updates) for the rest.
+async fn notify_all(
+    repo: &str,
+    notifiers: &[Notifier],
+    candidate: &Candidate,
+    client: &reqwest::Client,
+    args: &Args,
+) -> Result<()> {
+    let mut sent = Vec::new();
+    let mut failed = Vec::new();
+    let buttons = if args.asset_buttons {
+        &candidate.assets[..candidate.assets.len().min(MAX_ASSET_BUTTONS)]
+    } else {
+        &[]
+    };
+    let window = Duration::from_secs(args.dedupe_window_secs);
+    let detection = Detection::new(repo, candidate, args);
+    for notifier in notifiers {
+        let key = (repo.to_string(), candidate.tag.clone(), notifier.to_string());
+        if RECENTLY_SENT.contains(&key, window) {
+            info!(%repo, tag = %candidate.tag, target = %notifier, "already sent recently, skipping");
+            sent.push(notifier.to_string());
+            continue;
+        }
+        let text = notifier.format_message(&detection, args);
+        match notifier.send(client, text, buttons, args.dry_run).await {
+            Ok(()) => {
+                METRICS.notifications_sent.with_label_values(&[notifier.kind()]).inc();
+                RECENTLY_SENT.insert(key, window);
+                sent.push(notifier.to_string());
+            }
+            Err(e) => {
+                error!(%repo, target = %notifier, error=?e, "notification failed");
+                failed.push(notifier.to_string());
+            }
+        }
+    }
+    if let Some(path) = &args.event_log {
+        let event = DetectionEvent {
+            timestamp: Utc::now(),
+            repo,
+            tag: &candidate.tag,
+            prev_tag: candidate.previous.as_deref(),
+            kind: candidate.kind,
+            notified_targets: &sent,
+        };
+        if let Err(e) = append_event(path, &event) {
+            warn!(%repo, path = %path.display(), error=?e, "writing the event log failed");
+        }
+    }
+    if sent.is_empty() {
+        anyhow::bail!("notification failed for all targets: {:?}", failed);
+    }
+    info!(%repo, ?sent, ?failed, "notification delivered");
+    Ok(())
+}
+
+/// One line of `--event-log`.
+#[derive(Debug, Serialize)]
+struct DetectionEvent<'a> {
+    timestamp: DateTime<Utc>,
+    repo: &'a str,
+    tag: &'a str,
+    prev_tag: Option<&'a str>,
+    kind: Kind,
+    /// Empty when every target failed
+    notified_targets: &'a [String],
+}
+
+/// Serializes appends, so concurrent checks can't interleave their lines.
+static EVENT_LOG_LOCK: Mutex<()> = Mutex::new(());
+
+/// Appends `event` to `path` as one line, written with a single call.
+fn append_event(path: &Path, event: &DetectionEvent) -> Result<()> {
+    let mut line = serde_json::to_vec(event)?;
+    line.push(b'\n');
+    let _guard = EVENT_LOG_LOCK.lock().expect("event log lock poisoned");
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("opening event log {}", path.display()))?;
+    file.write_all(&line)?;
+    Ok(())
+}
+
+/// A tag that may be announced. `notes` is only set for release detections,
+/// `commit_sha` only for raw tags. Branch commits use the SHA as `tag` and
+/// the commit subject as `title`; workflow runs the run id and conclusion.
+#[derive(Debug, Clone)]
+struct Candidate {
+    tag: String,
+    kind: Kind,
+    notes: Option<String>,
+    commit_sha: Option<String>,
+    title: Option<String>,
+    /// Link to show instead of the tag's release page
+    url: Option<String>,
+    /// What was last seen before this one, for the compare link
+    previous: Option<String>,
+    /// Download links of a release's assets
+    assets: Vec<LinkButton>,
+    /// Login of whoever published the release
+    author: Option<String>,
+    /// The release's title, when it has one other than the tag
+    release_name: Option<String>,
+    /// Found while nothing had been seen for the repo yet
+    initial: bool,
+    /// Marked as a pre-release on GitHub
+    prerelease: bool,
+    /// The pre-release seen earlier that this stable version promotes
+    promoted_from: Option<String>,
+    /// Whether the tag is signed and verified, when --show-verification checked it
+    verified: Option<bool>,
+}
+
+impl Candidate {
+    /// A `kind` detection of `tag` with nothing else known about it yet.
+    fn new(tag: impl Into<String>, kind: Kind) -> Self {
+        Self {
+            tag: tag.into(),
+            kind,
+            notes: None,
+            commit_sha: None,
+            title: None,
+            url: None,
+            previous: None,
+            assets: Vec::new(),
+            author: None,
+            release_name: None,
+            initial: false,
+            prerelease: false,
+            promoted_from: None,
+            verified: None,
+        }
+    }
+
+    /// A pre-release by its semver pre segment or GitHub's flag.
+    fn is_prerelease(&self) -> bool {
+        self.prerelease || parse_version(&self.tag).is_some_and(|v| !v.pre.is_empty())
+    }
+
+    /// The newest of `prereleases` this stable version is the final release
+    /// of (`v2.0.0` for `v2.0.0-rc.3`).
+    fn promotes(&self, prereleases: &[String]) -> Option<String> {
+        if self.is_prerelease() || !matches!(self.kind, Kind::Release | Kind::Tag) {
+            return None;
+        }
+        let version = parse_version(&self.tag)?;
+        let base = (version.major, version.minor, version.patch);
+        prereleases
+            .iter()
+            .rev()
+            .find(|t| parse_version(t).is_some_and(|v| (v.major, v.minor, v.patch) == base))
+            .cloned()
+    }
+}
+
+/// How many pre-release tags are kept per repo for spotting their promotion.
+const RECENT_PRERELEASES: usize = 10;
+
+/// Adds `tag` to the most recent pre-releases, dropping the oldest.
+fn remember_prerelease(prereleases: &mut Vec<String>, tag: &str) {
+    if prereleases.iter().any(|t| t == tag) {
+        return;
+    }
+    prereleases.push(tag.to_string());
+    if prereleases.len() > RECENT_PRERELEASES {
+        prereleases.remove(0);
+    }
+}
+
+/// Whether a detection is the current version of a repo that just started
+/// being watched, something it released since, or the stable release of a
+/// pre-release it announced before.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Change {
+    Initial,
+    Update,
+    Promotion,
+}
+
+impl Change {
+    fn of(candidate: &Candidate) -> Self {
+        if candidate.initial {
+            Change::Initial
+        } else if candidate.promoted_from.is_some() {
+            Change::Promotion
+        } else {
+            Change::Update
+        }
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Change::Initial => "initial",
+            Change::Update => "update",
+            Change::Promotion => "promotion",
+        })
+    }
+}
+
+/// Whether a tag was found via a GitHub release or as a raw tag, or is a new
+/// commit on a watched branch or a finished workflow run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Kind {
+    Release,
+    Tag,
+    Commit,
+    Workflow,
+}
+
+/// JSON body sent to `--webhook-url`.
+#[derive(Debug, Serialize)]
+struct WebhookEvent<'a> {
+    repo: &'a str,
+    tag: &'a str,
+    url: &'a str,
+    kind: Kind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    commit_sha: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    title: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    compare_url: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    promoted_from: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    verified: Option<bool>,
+    detected_at: DateTime<Utc>,
+}
+
+/// A detection as every notifier gets it, with its links worked out and its
+/// notes already trimmed to --notes-max-chars (and only set with --include-notes).
+#[derive(Debug)]
+struct Detection<'a> {
+    repo: &'a str,
+    tag: &'a str,
+    prev_tag: Option<&'a str>,
+    url: String,
+    kind: Kind,
+    change: Change,
+    notes: Option<String>,
+    title: Option<&'a str>,
+    release_name: Option<&'a str>,
+    promoted_from: Option<&'a str>,
+    verified: Option<bool>,
+    commit_sha: Option<&'a str>,
+    commit_url: Option<String>,
+    compare_url: Option<String>,
+}
+
+impl<'a> Detection<'a> {
+    fn new(repo: &'a str, candidate: &'a Candidate, args: &Args) -> Self {
+        let tag = candidate.tag.as_str();
+        let path = repo.split(['@', '#']).next().unwrap_or(repo);
+        let compare_url = match (&candidate.previous, candidate.kind) {
+            _ if !on_github(repo) => None,
+            (Some(old), Kind::Release | Kind::Tag | Kind::Commit) => {
+                Some(format!("{}/{path}/compare/{old}...{tag}", github_web_url(args)))
+            }
+            _ => None,
+        };
+        let commit_url = candidate
+            .commit_sha
+            .as_ref()
+            .map(|sha| format!("{}/{path}/commit/{sha}", github_web_url(args)));
+        let notes = candidate
+            .notes
+            .as_deref()
+            .map(str::trim)
+            .filter(|n| args.include_notes && !n.is_empty())
+            .map(|n| truncate_chars(n, args.notes_max_chars));
+        Self {
+            repo,
+            tag,
+            prev_tag: candidate.previous.as_deref(),
+            url: candidate_url(repo, candidate, args),
+            kind: candidate.kind,
+            change: Change::of(candidate),
+            notes,
+            title: candidate.title.as_deref(),
+            release_name: candidate.release_name.as_deref(),
+            promoted_from: candidate.promoted_from.as_deref(),
+            verified: candidate.verified,
+            commit_sha: candidate.commit_sha.as_deref(),
+            commit_url,
+            compare_url,
+        }
+    }
+
+    /// The tag as shown in messages: commits by their short SHA, workflow
+    /// runs by their conclusion.
+    fn label(&self) -> &str {
+        match self.kind {
+            Kind::Commit => &self.tag[..self.tag.len().min(7)],
+            Kind::Workflow => self.title.unwrap_or("unknown"),
+            _ => self.tag,
+        }
+    }
+
+    /// How --show-verification marks the tag, if it was checked.
+    fn verification(&self, emoji: bool) -> Option<&'static str> {
+        self.verified.map(|verified| match (verified, emoji) {
+            (true, true) => "\u{2705} verified",
+            (false, true) => "\u{26A0}\u{FE0F} unsigned",
+            (true, false) => "verified",
+            (false, false) => "unsigned",
+        })
+    }
+
+    fn noun(&self) -> &'static str {
+        match self.kind {
+            _ if self.promoted_from.is_some() => "stable release",
+            Kind::Commit => "commit",
+            Kind::Workflow => "workflow result",
+            _ => "tag",
+        }
+    }
+}
+
+impl Notifier {
+    /// Formats the message for this target: Telegram gets text escaped for
+    /// its parse mode, Slack mrkdwn, Discord its own (unescaped) markdown,
+    /// email plain text under a subject line, Matrix plain text (linkified
+    /// when sent), ntfy plain text wrapped in an [`NtfyMessage`], Teams a
+    /// MessageCard and webhooks a JSON event.
+    fn format_message(&self, event: &Detection, args: &Args) -> String {
+        let Detection { repo, tag, ref url, .. } = *event;
+        let noun = event.noun();
+        let label = match event.release_name.filter(|_| args.release_names) {
+            Some(name) => format!("{name} ({tag})"),
+            None => event.label().to_string(),
+        };
+        let label = label.as_str();
+        let path = repo.split(['@', '#']).next().unwrap_or(repo);
+        if let Notifier::Stdout = self {
+            return render_template(&args.stdout_format, |key| match key {
+                "repo" => Some(repo.to_string()),
+                "tag" => Some(tag.to_string()),
+                "url" => Some(url.clone()),
+                "kind" => Some(state::kind_name(event.kind).to_string()),
+                "title" => Some(event.title.unwrap_or_default().to_string()),
+                _ => None,
+            });
+        }
+        if let Notifier::Webhook { .. } = self {
+            let payload = WebhookEvent {
+                repo,
+                tag,
+                url,
+                kind: event.kind,
+                commit_sha: event.commit_sha,
+                title: event.title,
+                compare_url: event.compare_url.as_deref(),
+                promoted_from: event.promoted_from,
+                verified: event.verified,
+                detected_at: Utc::now(),
+            };
+            return serde_json::to_string(&payload).expect("webhook event serializes");
+        }
+        let notes = event.notes.clone();
+        if let Notifier::Teams { .. } = self {
+            return teams_card(event, label, notes);
+        }
+        // a workflow's title (its conclusion) is already the label
+        let verification = event.verification(!args.no_emoji);
+        let title = |escape: fn(&str) -> String| {
+            let title = match (event.kind, event.promoted_from) {
+                (_, Some(pre)) => format!(" promoted from {}", escape(pre)),
+                (Kind::Workflow, _) => String::new(),
+                _ => event.title.map(|t| format!(" {}", escape(t))).unwrap_or_default(),
+            };
+            match verification {
+                Some(badge) => format!("{title} {}", escape(badge)),
+                None => title,
+            }
+        };
+
+        let icon = if args.no_emoji { "" } else { "\u{1F680} " };
+        let email_subject = || match event.kind {
+            _ if event.promoted_from.is_some() => format!("Stable release: {repo} {tag}"),
+            Kind::Commit => format!("New commit: {repo} {label}"),
+            Kind::Workflow => format!("Workflow {label}: {repo}"),
+            _ => format!("New release: {repo} {tag}"),
+        };
+
+        let (mut msg, notes) = if let Some(template) = &args.message_template {
+            // values are escaped for the target, the template is taken as written
+            let escape = escape_for(self);
+            let (owner, name) = path.split_once('/').unwrap_or((path, ""));
+            let change = event.change.to_string();
+            let body = render_template(template, |key| {
+                let value = match key {
+                    "repo" => repo,
+                    "tag" => tag,
+                    "url" => url,
+                    "prev_tag" => event.prev_tag.unwrap_or_default(),
+                    "owner" => owner,
+                    "name" => name,
+                    "kind" => state::kind_name(event.kind),
+                    "release_name" => event.release_name.unwrap_or_default(),
+                    "promoted_from" => event.promoted_from.unwrap_or_default(),
+                    "verified" => event.verification(false).unwrap_or_default(),
+                    "change" => &change,
+                    "emoji" => icon.trim_end(),
+                    _ => return None,
+                };
+                Some(escape(value))
+            });
+            let msg = match self {
+                Notifier::Email { .. } => format!("{}\n{body}", email_subject()),
+                _ => body,
+            };
+            (msg, notes.map(|n| escape(&n)))
+        } else {
+            match self {
+                Notifier::Telegram { config, .. } => match config.parse_mode {
+                    TgParseMode::MarkdownV2 => (
+                        format!(
+                            "{icon}New {noun} in *{}*: `{}`{}\n{}",
+                            escape_markdown_v2(repo),
+                            escape_markdown_v2(label),
+                            title(escape_markdown_v2),
+                            escape_markdown_v2(url),
+                        ),
+                        notes.map(|n| escape_markdown_v2(&n)),
+                    ),
+                    TgParseMode::Html => (
+                        format!(
+                            "{icon}New {noun} in <b>{}</b>: <code>{}</code>{}\n<a href=\"{url}\">{url}</a>",
+                            escape_html(repo),
+                            escape_html(label),
+                            title(escape_html),
+                            url = escape_html(url),
+                        ),
+                        notes.map(|n| escape_html(&n)),
+                    ),
+                    TgParseMode::Plain => (
+                        format!(
+                            "{icon}New {noun} in {repo}: {label}{}\n{url}",
+                            title(str::to_string)
+                        ),
+                        notes,
+                    ),
+                },
+                Notifier::Slack { .. } => (
+                    format!(
+                        "{icon}New {noun} in *{}*: `{}`{}\n{}",
+                        escape_slack(repo),
+                        escape_slack(label),
+                        title(escape_slack),
+                        escape_slack(url),
+                    ),
+                    notes.map(|n| escape_slack(&n)),
+                ),
+                _ if matches!(self, Notifier::Matrix { .. }) || self.takes_ntfy_message() => (
+                    format!("{icon}New {noun} in {repo}: {label}{}\n{url}", title(str::to_string)),
+                    notes,
+                ),
+                Notifier::Email { .. } => {
+                    let subject = email_subject();
+                    (
+                        format!(
+                            "{subject}\n{icon}New {noun} in {repo}: {label}{}\n{url}",
+                            title(str::to_string)
+                        ),
+                        notes,
+                    )
+                }
+                _ => (
+                    format!(
+                        "{icon}New {noun} in **{repo}**: `{label}`{}\n{url}",
+                        title(str::to_string)
+                    ),
+                    notes,
+                ),
+            }
+        };
+        // a template replaces the commit and compare lines too
+        let templated = args.message_template.is_some();
+        let commit = event.commit_sha.zip(event.commit_url.as_ref()).filter(|_| !templated);
+        if let Some((sha, commit_url)) = commit {
+            // raw tags can be ambiguous (and workflow runs built some commit), so
+            // point at the exact commit too
+            let short = &sha[..sha.len().min(7)];
+            msg.push_str(&match self {
+                Notifier::Telegram { config, .. } => match config.parse_mode {
+                    TgParseMode::MarkdownV2 => {
+                        format!("\ncommit `{short}`: {}", escape_markdown_v2(commit_url))
+                    }
+                    TgParseMode::Html => format!(
+                        "\ncommit <code>{short}</code>: <a href=\"{url}\">{url}</a>",
+                        url = escape_html(commit_url),
+                    ),
+                    TgParseMode::Plain => format!("\ncommit {short}: {commit_url}"),
+                },
+                Notifier::Slack { .. } => {
+                    format!("\ncommit `{short}`: {}", escape_slack(commit_url))
+                }
+                Notifier::Email { .. } | Notifier::Matrix { .. } => {
+                    format!("\ncommit {short}: {commit_url}")
+                }
+                _ if self.takes_ntfy_message() => format!("\ncommit {short}: {commit_url}"),
+                _ => format!("\ncommit `{short}`: {commit_url}"),
+            });
+        }
+        if let Some(compare_url) = event.compare_url.as_ref().filter(|_| !templated) {
+            msg.push_str(&match self {
+                Notifier::Telegram { config, .. } => match config.parse_mode {
+                    TgParseMode::MarkdownV2 => {
+                        format!("\nchanges: {}", escape_markdown_v2(compare_url))
+                    }
+                    TgParseMode::Html => format!(
+                        "\nchanges: <a href=\"{url}\">{url}</a>",
+                        url = escape_html(compare_url)
+                    ),
+                    TgParseMode::Plain => format!("\nchanges: {compare_url}"),
+                },
+                Notifier::Slack { .. } => format!("\nchanges: {}", escape_slack(compare_url)),
+                _ => format!("\nchanges: {compare_url}"),
+            });
+        }
+        if let Some(notes) = notes {
+            msg.push_str("\n\n");
+            msg.push_str(&notes);
+        }
+        if self.takes_ntfy_message() {
+            let message = NtfyMessage {
+                title: format!("New {noun} in {repo}"),
+                message: msg,
+                click: url.clone(),
+            };
+            return serde_json::to_string(&message).expect("ntfy message serializes");
+        }
+        msg
+    }
+}
+
+/// Where a detection links to: its own URL, else the tag's release page.
+fn candidate_url(repo: &str, candidate: &Candidate, args: &Args) -> String {
+    candidate.url.clone().unwrap_or_else(|| {
+        format!("{}/{repo}/releases/tag/{}", github_web_url(args), candidate.tag)
+    })
+}
+
+/// Escapes interpolated text for how `notifier` formats messages.
+fn escape_for(notifier: &Notifier) -> fn(&str) -> String {
+    match notifier {
+        Notifier::Telegram { config, .. } => match config.parse_mode {
+            TgParseMode::MarkdownV2 => escape_markdown_v2,
+            TgParseMode::Html => escape_html,
+            TgParseMode::Plain => str::to_string,
+        },
+        Notifier::Slack { .. } => escape_slack,
+        _ => str::to_string,
+    }
+}
+
+/// A Teams MessageCard: the repo as title, the tag (and notes) as text and
+/// buttons to the release and compare pages.
+fn teams_card(event: &Detection, label: &str, notes: Option<String>) -> String {
+    let (noun, view) = match event.kind {
+        _ if event.promoted_from.is_some() => ("stable release", "View Release"),
+        Kind::Release => ("release", "View Release"),
+        Kind::Tag => ("tag", "View Tag"),
+        Kind::Commit => ("commit", "View Commit"),
+        Kind::Workflow => ("workflow result", "View Run"),
+    };
+    let mut text = match (event.kind, event.title, event.promoted_from) {
+        (_, _, Some(pre)) => format!("{label} promoted from {pre}"),
+        (Kind::Workflow, _, _) | (_, None, _) => label.to_string(),
+        (_, Some(title), _) => format!("{label} {title}"),
+    };
+    if let Some(badge) = event.verification(true) {
+        text.push(' ');
+        text.push_str(badge);
+    }
+    if let Some(notes) = notes {
+        text.push_str("\n\n");
+        text.push_str(&notes);
+    }
+    let open = |name: &str, uri: &str| {
+        serde_json::json!({
+            "@type": "OpenUri",
+            "name": name,
+            "targets": [{ "os": "default", "uri": uri }],
+        })
+    };
+    let mut actions = vec![open(view, &event.url)];
+    if let Some(compare_url) = &event.compare_url {
+        actions.push(open("View Changes", compare_url));
+    }
+    let title = format!("New {noun} in {}", event.repo);
+    serde_json::json!({
+        "@type": "MessageCard",
+        "@context": "https://schema.org/extensions",
+        "summary": title,
+        "title": title,
+        "text": text,
+        "potentialAction": actions,
+    })
+    .to_string()
+}
+
+/// Escapes the three characters Slack mrkdwn treats as control characters.
+fn escape_slack(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+/// Escapes text for Telegram's HTML parse mode (also safe inside attributes).
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Escapes every character Telegram's MarkdownV2 treats as reserved.
+fn escape_markdown_v2(s: &str) -> String {
+    const RESERVED: &[char] = &[
+        '\\', '_', '*', '[', ']', '(', ')', '~', '`', '>', '#', '+', '-', '=', '|', '{', '}', '.',
+        '!',
+    ];
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        if RESERVED.contains(&c) {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Web (not API) root for links: github.com, or the Enterprise host whose
+/// API lives under `/api/v3`.
+fn github_web_url(args: &Args) -> &str {
+    match &args.github_base_url {
+        Some(base) => {
+            let base = base.trim_end_matches('/');
+            base.strip_suffix("/api/v3").unwrap_or(base)
+        }
+        None => "https://github.com",
+    }
+}
+
+/// Replaces each `{key}` in `template` with `value(key)`, in one pass so
+/// values are never expanded again. Unknown keys are left as written.
+fn render_template(template: &str, value: impl Fn(&str) -> Option<String>) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        match after.find('}').and_then(|end| Some((end, value(&after[..end])?))) {
+            Some((end, v)) => {
+                out.push_str(&v);
+                rest = &after[end + 1..];
+            }
+            None => {
+                out.push('{');
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Truncates `s` to at most `max` chars, ending with `…` when it was cut.
+fn truncate_chars(s: &str, max: usize) -> String {
+    if s.chars().count() <= max {
+        return s.to_string();
+    }
+    let mut out: String = s.chars().take(max.saturating_sub(1)).collect();
+    out.push('…');
+    out
+}
+
+/// Items fetched per GitHub request: --page-size, else just the newest one
+/// unless a feature has to look further back than it.
+fn page_size(args: &Args) -> u8 {
+    let looks_back = args.max_catchup > 1
+        || args.order_by != OrderBy::Created
+        || args.since.is_some()
+        || args.notify_on_retraction;
+    args.page_size.unwrap_or(if looks_back { 30 } else { 1 })
+}
+
+async fn release_tags(
+    octo: &octocrab::Octocrab,
+    owner: &str,
+    repo: &str,
+    etag: Option<String>,
+    args: &Args,
+) -> Result<Fetched<Vec<Candidate>>> {
+    // list releases: newest first by creation date
+    let route = format!("/repos/{owner}/{repo}/releases?per_page={}", page_size(args));
+    let fetched: Fetched<Vec<models::repos::Release>> =
+        github::get_json(octo, &route, etag.as_deref()).await?;
+
+    // An empty list falls through to raw tags; don't hand back its ETag, or the
+    // next poll's 304 would skip the tag check.
+    if let Fetched::Modified { value, .. } = &fetched {
+        if value.is_empty() {
+            anyhow::bail!("no releases found with tag_name");
+        }
+    }
+
+    // filtering may leave nothing; that's "no change", not a reason to fall
+    // back to raw tags (which would include the filtered versions)
+    Ok(fetched.map(|releases| {
+        let mut releases: Vec<_> =
+            releases.into_iter().filter(|r| release_wanted(r, args)).collect();
+        order_releases(&mut releases, args.order_by, |r| &r.tag_name, |r| r.published_at);
+        releases.into_iter().map(release_candidate).collect()
+    }))
+}
+
+/// The release the maintainers marked "Latest", which needn't be the newest
+/// one. Fails (falling through to raw tags) when the repo has no releases.
+async fn latest_release(
+    octo: &octocrab::Octocrab,
+    owner: &str,
+    repo: &str,
+    etag: Option<String>,
+    args: &Args,
+) -> Result<Fetched<Vec<Candidate>>> {
+    let route = format!("/repos/{owner}/{repo}/releases/latest");
+    let fetched: Fetched<models::repos::Release> =
+        github::get_json(octo, &route, etag.as_deref()).await?;
+    Ok(fetched.map(|release| {
+        Some(release)
+            .filter(|r| release_wanted(r, args))
+            .map(release_candidate)
+            .into_iter()
+            .collect()
+    }))
+}
+
+fn release_candidate(r: models::repos::Release) -> Candidate {
+    let release_name = r.name.filter(|n| !n.trim().is_empty() && *n != r.tag_name);
+    Candidate {
+        release_name,
+        prerelease: r.prerelease,
+        author: Some(r.author.login),
+        notes: r.body,
+        assets: r
+            .assets
+            .into_iter()
+            .map(|a| LinkButton {
+                text: a.name,
+                url: a.browser_download_url.to_string(),
+            })
+            .collect(),
+        ..Candidate::new(r.tag_name, Kind::Release)
+    }
+}
+
+fn release_wanted(r: &models::repos::Release, args: &Args) -> bool {
+    if let Some(since) = args.since {
+        if r.published_at.is_none_or(|t| t <= since) {
+            return false;
+        }
+    }
+    if args.stable_only {
+        !r.prerelease && !r.draft
+    } else if args.prerelease_only {
+        r.prerelease && !r.draft
+    } else {
+        true
+    }
+}
+
+async fn raw_tags(
+    octo: &octocrab::Octocrab,
+    owner: &str,
+    repo: &str,
+    etag: Option<String>,
+    page_size: u8,
+) -> Result<Fetched<Vec<Candidate>>> {
+    // list tags: GitHub returns most recent commit/tag first, which is not
+    // necessarily the semver-highest, so fetch a full page and sort it
+    let route = format!("/repos/{owner}/{repo}/tags?per_page={page_size}");
+    let fetched: Fetched<Vec<models::repos::Tag>> =
+        github::get_json(octo, &route, etag.as_deref()).await?;
+
+    if let Fetched::Modified { value, .. } = &fetched {
+        if value.is_empty() {
+            anyhow::bail!("no raw tags found");
+        }
+    }
+
+    Ok(fetched.map(|tags| {
+        semver_ordered_tags(&tags, |t| &t.name)
+            .into_iter()
+            .map(|tag| Candidate {
+                commit_sha: Some(tag.commit.sha.clone()),
+                ..Candidate::new(tag.name.as_str(), Kind::Tag)
+            })
+            .collect()
+    }))
+}
+
+/// The newest commits on `branch`, newest first.
+async fn branch_commits(
+    octo: &octocrab::Octocrab,
+    owner: &str,
+    repo: &str,
+    branch: &str,
+    etag: Option<String>,
+    page_size: u8,
+) -> Result<Fetched<Vec<Candidate>>> {
+    // branch names may hold `&`, `#`, `+` and the like
+    let sha = github::encode(branch);
+    let route = format!("/repos/{owner}/{repo}/commits?sha={sha}&per_page={page_size}");
+    let fetched: Fetched<Vec<models::repos::RepoCommit>> =
+        github::get_json(octo, &route, etag.as_deref()).await?;
+
+    Ok(fetched.map(|commits| {
+        commits
+            .into_iter()
+            .map(|c| Candidate {
+                title: c.commit.message.lines().next().map(str::to_string),
+                url: Some(c.html_url),
+                ..Candidate::new(c.sha, Kind::Commit)
+            })
+            .collect()
+    }))
+}
+
+/// Returns tags ordered highest semver first (an optional leading `v` is
+/// ignored). Falls back to the given order when no tag parses as semver.
+fn semver_ordered_tags<T>(tags: &[T], name: impl Fn(&T) -> &str) -> Vec<&T> {
+    let mut versioned: Vec<(semver::Version, &T)> = tags
+        .iter()
+        .filter_map(|t| match parse_version(name(t)) {
+            Some(v) => Some((v, t)),
+            None => {
+                debug!(tag = %name(t), "ignoring non-semver tag");
+                None
+            }
+        })
+        .collect();
+
+    if versioned.is_empty() {
+        return tags.iter().collect();
+    }
+
+    versioned.sort_by(|(a, _), (b, _)| b.cmp(a));
+    versioned.into_iter().map(|(_, tag)| tag).collect()
+}
+
+fn parse_version(tag: &str) -> Option<semver::Version> {
+    let v = tag.strip_prefix('v').unwrap_or(tag);
+    semver::Version::parse(v).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Args watching `o/r`, with `extra` flags.
+    pub(crate) fn test_args(extra: &[&str]) -> Args {
+        Args::parse_from(["github-tag-watcher", "--repos", "o/r"].iter().chain(extra))
+    }
+
+    /// A Discord target, for checking the messages it would get.
+    pub(crate) fn discord() -> Notifier {
+        Notifier::Discord { webhook_url: String::new() }
+    }
+
+    #[test]
+    fn escape_markdown_v2_escapes_reserved_chars() {
+        assert_eq!(
+            escape_markdown_v2("v1.2.3-rc.1 (beta)"),
+            r"v1\.2\.3\-rc\.1 \(beta\)"
+        );
+        assert_eq!(escape_markdown_v2(r"a_b`c\d"), r"a\_b\`c\\d");
+        assert_eq!(escape_markdown_v2("plain"), "plain");
+    }
+
+    #[test]
+    fn escape_html_escapes_markup() {
+        assert_eq!(
+            escape_html(r#"<b>a & "b"</b>"#),
+            "&lt;b&gt;a &amp; &quot;b&quot;&lt;/b&gt;"
+        );
+        assert_eq!(escape_html("my_org/my_repo"), "my_org/my_repo");
+    }
+
+    #[test]
+    fn render_template_fills_known_keys_once() {
+        let value = |key: &str| match key {
+            "repo" => Some("o/r".to_string()),
+            "tag" => Some("{repo}".to_string()),
+            _ => None,
+        };
+        assert_eq!(render_template("{repo}: {tag}", value), "o/r: {repo}");
+        assert_eq!(render_template("{nope} {repo} {", value), "{nope} o/r {");
+    }
+
+    #[test]
+    fn no_emoji_drops_the_rocket() {
+        let discord = discord();
+        let candidate = Candidate::new("v1.0.0", Kind::Tag);
+        let message = |args: &Args| {
+            discord.format_message(&Detection::new("o/r", &candidate, args), args)
+        };
+        let args = test_args(&[]);
+        assert!(message(&args).starts_with("\u{1F680} New tag"));
+        let args = test_args(&["--no-emoji"]);
+        assert!(message(&args).starts_with("New tag"));
+        let args = test_args(&["--message-template", "{emoji} {name} {tag}"]);
+        assert_eq!(message(&args), "\u{1F680} r v1.0.0");
+    }
+
+    #[test]
+    fn detection_works_out_links_for_every_notifier() {
+        let candidate = Candidate {
+            notes: Some("  fixes  ".into()),
+            commit_sha: Some("abcdef123456".into()),
+            previous: Some("v1.0.0".into()),
+            ..Candidate::new("v1.1.0", Kind::Tag)
+        };
+        let args = test_args(&["--include-notes"]);
+        let detection = Detection::new("o/r", &candidate, &args);
+        assert_eq!(detection.url, "https://github.com/o/r/releases/tag/v1.1.0");
+        assert_eq!(
+            detection.compare_url.as_deref(),
+            Some("https://github.com/o/r/compare/v1.0.0...v1.1.0")
+        );
+        assert_eq!(detection.notes.as_deref(), Some("fixes"));
+        assert_eq!(detection.change, Change::Update);
+        let slack = Notifier::Slack { webhook_url: String::new() };
+        assert_eq!(
+            slack.format_message(&detection, &args),
+            "\u{1F680} New tag in *o/r*: `v1.1.0`\n\
+             https://github.com/o/r/releases/tag/v1.1.0\n\
+             commit `abcdef1`: https://github.com/o/r/commit/abcdef123456\n\
+             changes: https://github.com/o/r/compare/v1.0.0...v1.1.0\n\nfixes"
+        );
+    }
+
+    #[test]
+    fn release_names_show_before_the_tag() {
+        let candidate = Candidate {
+            release_name: Some("2024.1 LTS".into()),
+            ..Candidate::new("v2024.1", Kind::Release)
+        };
+        let discord = discord();
+        let message = |args: &[&str]| {
+            let args = test_args(&[&["--no-emoji"], args].concat());
+            discord.format_message(&Detection::new("o/r", &candidate, &args), &args)
+        };
+        assert!(message(&[]).starts_with("New tag in **o/r**: `v2024.1`\n"));
+        assert!(message(&["--release-names"])
+            .starts_with("New tag in **o/r**: `2024.1 LTS (v2024.1)`\n"));
+        assert_eq!(
+            message(&["--message-template", "{release_name}: {tag}"]),
+            "2024.1 LTS: v2024.1"
+        );
+    }
+
+    #[test]
+    fn stable_release_promotes_its_latest_pre_release() {
+        let candidate = |tag: &str| Candidate::new(tag, Kind::Release);
+        let seen = ["v1.9.0-rc.1", "v2.0.0-beta.1", "v2.0.0-rc.3"].map(String::from);
+        assert_eq!(candidate("v2.0.0").promotes(&seen).as_deref(), Some("v2.0.0-rc.3"));
+        assert_eq!(candidate("v2.0.1").promotes(&seen), None);
+        assert_eq!(candidate("v2.0.0-rc.4").promotes(&seen), None);
+
+        let promoted = Candidate {
+            promoted_from: Some("v2.0.0-rc.3".into()),
+            ..candidate("v2.0.0")
+        };
+        let args = test_args(&["--no-emoji"]);
+        let discord = discord();
+        let message = discord.format_message(&Detection::new("o/r", &promoted, &args), &args);
+        assert!(message
+            .starts_with("New stable release in **o/r**: `v2.0.0` promoted from v2.0.0-rc.3\n"));
+
+        let mut recent = Vec::new();
+        for n in 0..12 {
+            remember_prerelease(&mut recent, &format!("v1.0.0-rc.{n}"));
+        }
+        remember_prerelease(&mut recent, "v1.0.0-rc.11");
+        assert_eq!(recent.len(), RECENT_PRERELEASES);
+        assert_eq!(recent[0], "v1.0.0-rc.2");
+    }
+
+    #[test]
+    fn verification_marks_the_message() {
+        let candidate = |verified| Candidate {
+            verified,
+            ..Candidate::new("v1.0.0", Kind::Tag)
+        };
+        let args = test_args(&[]);
+        let slack = Notifier::Slack { webhook_url: String::new() };
+        let message = |verified| {
+            let candidate = candidate(verified);
+            slack.format_message(&Detection::new("o/r", &candidate, &args), &args)
+        };
+        assert!(message(Some(true)).contains("`v1.0.0` \u{2705} verified\n"));
+        assert!(message(Some(false)).contains("`v1.0.0` \u{26A0}\u{FE0F} unsigned\n"));
+        assert!(message(None).contains("`v1.0.0`\n"));
+    }
+
+    #[test]
+    fn retraction_message_names_the_removed_tag() {
+        let args = test_args(&[]);
+        let discord = discord();
+        assert_eq!(
+            retraction_message("o/r", "v1.1.0", &args, &discord),
+            "\u{26A0}\u{FE0F} Tag v1.1.0 in o/r was removed\nhttps://github.com/o/r/tags"
+        );
+        let slack = Notifier::Slack { webhook_url: String::new() };
+        assert!(retraction_message("o/r", "<b>", &args, &slack).contains("Tag &lt;b&gt; in"));
+    }
+
+    #[test]
+    fn order_releases_by_semver_or_publish_date() {
+        let at = |s: &str| s.parse::<DateTime<Utc>>().ok();
+        let listed = [
+            ("v1.10.0", at("2024-01-01T00:00:00Z")),
+            ("v2.0.0", at("2024-03-01T00:00:00Z")),
+            ("nightly", None),
+            ("v1.9.1", at("2024-04-01T00:00:00Z")),
+        ];
+        let order = |order| {
+            let mut releases = listed;
+            order_releases(&mut releases, order, |r| r.0, |r| r.1);
+            releases.map(|r| r.0)
+        };
+        assert_eq!(order(OrderBy::Created), ["v1.10.0", "v2.0.0", "nightly", "v1.9.1"]);
+        assert_eq!(order(OrderBy::Semver), ["v2.0.0", "v1.10.0", "v1.9.1", "nightly"]);
+        assert_eq!(order(OrderBy::Published), ["v1.9.1", "v2.0.0", "v1.10.0", "nightly"]);
+    }
+
+    #[test]
+    fn heartbeat_message_counts_repos() {
+        let args = test_args(&["--no-emoji"]);
+        let discord = discord();
+        assert_eq!(heartbeat_message(3, &args, &discord), "Still watching 3 repos, no changes");
+        assert_eq!(heartbeat_message(1, &args, &discord), "Still watching 1 repo, no changes");
+        let version = env!("CARGO_PKG_VERSION");
+        assert_eq!(
+            startup_message(2, &args, &discord),
+            format!("dockmasterbot {version} started, watching 2 repos, polling every 120s")
+        );
+    }
+
+    #[test]
+    fn sent_cache_remembers_keys_within_the_window() {
+        let cache = SentCache::default();
+        let key = ("o/r".to_string(), "v1.0.0".to_string(), "discord".to_string());
+        let window = Duration::from_secs(300);
+        assert!(!cache.contains(&key, window));
+        cache.insert(key.clone(), window);
+        assert!(cache.contains(&key, window));
+        assert!(!cache.contains(&key, Duration::ZERO));
+        let other = ("o/r".to_string(), "v1.0.0".to_string(), "slack".to_string());
+        assert!(!cache.contains(&other, window));
+    }
+
+    #[test]
+    fn enqueue_drops_the_oldest_past_the_cap() {
+        let detection = |tag: &str| QueuedDetection {
+            tag: tag.into(),
+            kind: Kind::Tag,
+            previous: None,
+            title: None,
+            url: None,
+            commit_sha: None,
+            notes: None,
+            release_name: None,
+            initial: false,
+            prerelease: false,
+            promoted_from: None,
+            verified: None,
+        };
+        let mut r = state::RepoState::default();
+        for tag in ["v1", "v2", "v3"] {
+            enqueue("o/r", &mut r, detection(tag), 2);
+        }
+        let tags: Vec<_> = r.queued.iter().map(|q| q.tag.as_str()).collect();
+        assert_eq!(tags, ["v2", "v3"]);
+    }
+
+    #[test]
+    fn in_cooldown_until_the_gap_passed() {
+        let now = Utc::now();
+        let ago = |secs| Some(now - chrono::Duration::seconds(secs));
+        assert!(in_cooldown(ago(30), 60, now));
+        assert!(!in_cooldown(ago(60), 60, now));
+        assert!(!in_cooldown(None, 60, now));
+    }
+
+    #[test]
+    fn unchanged_per_graphql_compares_what_rest_would_find() {
+        let newest = github::NewestTags {
+            latest_release: Some("v1.0.0".into()),
+            newest_release: Some("v1.1.0-rc.1".into()),
+            tags: vec!["v1.1.0-rc.1".into()],
+        };
+        let stored = |tag: &str, kind| state::RepoState {
+            last_seen_tag: Some(tag.into()),
+            kind: Some(kind),
+            ..Default::default()
+        };
+        assert!(unchanged_per_graphql(&newest, &stored("v1.1.0-rc.1", Kind::Release), false));
+        assert!(!unchanged_per_graphql(&newest, &stored("v1.1.0-rc.1", Kind::Release), true));
+        assert!(unchanged_per_graphql(&newest, &stored("v1.0.0", Kind::Release), true));
+        // a repo's first release is news even though its tag was seen
+        assert!(!unchanged_per_graphql(&newest, &stored("v1.1.0-rc.1", Kind::Tag), false));
+        let tags_only = github::NewestTags {
+            tags: vec!["v2.0.0".into(), "v1.9.0".into()],
+            ..Default::default()
+        };
+        assert!(unchanged_per_graphql(&tags_only, &stored("v2.0.0", Kind::Tag), false));
+        assert!(!unchanged_per_graphql(&tags_only, &state::RepoState::default(), false));
+        // a backport tagged last isn't what REST would report as newest
+        let backport = github::NewestTags {
+            tags: vec!["v1.9.1".into(), "v2.0.0".into()],
+            ..Default::default()
+        };
+        assert!(!unchanged_per_graphql(&backport, &stored("v1.9.1", Kind::Tag), false));
+        assert!(!unchanged_per_graphql(&backport, &stored("v2.0.0", Kind::Tag), false));
+    }
+
+    #[test]
+    fn in_window_wraps_past_midnight() {
+        let t = |s| parse_hhmm(s).unwrap();
+        assert!(in_window(t("23:30"), t("22:00"), t("07:00")));
+        assert!(in_window(t("06:59"), t("22:00"), t("07:00")));
+        assert!(!in_window(t("07:00"), t("22:00"), t("07:00")));
+        assert!(in_window(t("12:00"), t("09:00"), t("17:00")));
+        assert!(!in_window(t("17:00"), t("09:00"), t("17:00")));
+        assert!(parse_hhmm("25:00").is_err());
+        assert!(matches!(parse_timezone("+02:00"), Ok(Timezone::Fixed(_))));
+        assert!(parse_timezone("Mars/Olympus").is_err());
+    }
+
+    #[test]
+    fn owner_tokens_parse_as_owner_token_pairs() {
+        let args = test_args(&["--owner-token", "My-Org=ghp_a,other=ghp_b=c"]);
+        assert_eq!(
+            args.owner_tokens,
+            [("my-org".to_string(), "ghp_a".to_string()), ("other".into(), "ghp_b=c".into())]
+        );
+        assert!(parse_owner_token("=ghp_a").is_err());
+        assert!(parse_owner_token("my-org").is_err());
+    }
+
+    #[test]
+    fn secret_files_read_and_trim_the_named_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("secret");
+        std::fs::write(&path, "ghp_secret\n").unwrap();
+        let secrets = secret_files(|var| (var == "GITHUB_TOKEN_FILE").then(|| path.clone().into()));
+        assert_eq!(secrets.unwrap(), [("GITHUB_TOKEN", "ghp_secret".to_string())]);
+        std::fs::remove_file(&path).unwrap();
+
+        let missing = secret_files(|var| (var == "TG_BOT_TOKEN_FILE").then(|| path.clone().into()));
+        assert!(missing.unwrap_err().to_string().starts_with("reading TG_BOT_TOKEN_FILE"));
+    }
+
+    #[test]
+    fn parse_since_reads_rfc3339_in_any_offset() {
+        let since = parse_since("2024-05-01T02:00:00+02:00").unwrap();
+        assert_eq!(since, "2024-05-01T00:00:00Z".parse::<DateTime<Utc>>().unwrap());
+        assert!(parse_since("2024-05-01").is_err());
+    }
+
+    #[test]
+    fn page_size_is_capped_at_githubs_max_and_defaults_per_feature() {
+        let parse = |n: &str| {
+            Args::try_parse_from(["github-tag-watcher", "--repos", "o/r", "--page-size", n])
+        };
+        assert_eq!(page_size(&parse("50").unwrap()), 50);
+        assert!(parse("101").is_err());
+        assert!(parse("0").is_err());
+        // catch-up is on by default
+        let args = test_args(&[]);
+        assert_eq!(page_size(&args), 30);
+        let args = test_args(&["--max-catchup", "1"]);
+        assert_eq!(page_size(&args), 1);
+    }
+
+    #[test]
+    fn backoff_secs_doubles_up_to_the_cap() {
+        assert_eq!(backoff_secs(60, 0, 3, 3600), 60);
+        assert_eq!(backoff_secs(60, 2, 3, 3600), 60);
+        assert_eq!(backoff_secs(60, 3, 3, 3600), 120);
+        assert_eq!(backoff_secs(60, 4, 3, 3600), 240);
+        assert_eq!(backoff_secs(60, 40, 3, 3600), 3600);
+        // a cap below the regular interval never speeds a repo up
+        assert_eq!(backoff_secs(7200, 5, 3, 3600), 7200);
+    }
+
+    #[test]
+    fn min_version_filters_older_semver_tags_only() {
+        let args = test_args(&[]);
+        let entry = RepoConfig {
+            min_version: Some(semver::Version::new(2, 0, 0)),
+            ..RepoConfig::new("o/r")
+        };
+        assert_eq!(tag_filtered_out("v1.9.3", &entry, &args), Some("below min_version"));
+        assert_eq!(tag_filtered_out("v2.0.0", &entry, &args), None);
+        assert_eq!(tag_filtered_out("nightly", &entry, &args), None);
+    }
+
+    #[test]
+    fn allowed_authors_filters_releases_only() {
+        let entry = RepoConfig {
+            allowed_authors: Some(vec!["Octocat".into()]),
+            ..RepoConfig::new("o/r")
+        };
+        let release = |author: Option<&str>| Candidate {
+            author: author.map(String::from),
+            ..Candidate::new("v1.0.0", Kind::Release)
+        };
+        assert_eq!(author_filtered_out(&release(Some("octocat")), &entry), None);
+        assert_eq!(
+            author_filtered_out(&release(Some("release-bot")), &entry),
+            Some("author not in allowed_authors")
+        );
+        assert_eq!(author_filtered_out(&release(None), &entry), None);
+        assert_eq!(author_filtered_out(&release(Some("anyone")), &RepoConfig::new("o/r")), None);
+    }
+
+    #[test]
+    fn route_sends_pre_releases_to_their_channel() {
+        let args = test_args(&["--tg-bot-token", "t", "--tg-chat-id", "1"]);
+        let candidate = |tag: &str, prerelease| Candidate {
+            prerelease,
+            ..Candidate::new(tag, Kind::Release)
+        };
+        let chats = |entry: &RepoConfig, candidate: &Candidate| -> Vec<i64> {
+            let default = notifiers_for(entry, &args);
+            route(entry, candidate, &default, &args)
+                .iter()
+                .filter_map(|n| match n {
+                    Notifier::Telegram { chat_id, .. } => Some(*chat_id),
+                    _ => None,
+                })
+                .collect()
+        };
+        let entry = RepoConfig {
+            prerelease_chat_id: Some(config::ChatIds::Many(vec![3, 4])),
+            ..RepoConfig::new("o/r")
+        };
+        assert_eq!(chats(&entry, &candidate("v1.0.0", false)), [1]);
+        assert_eq!(chats(&entry, &candidate("v1.1.0-rc.1", false)), [3, 4]);
+        assert_eq!(chats(&entry, &candidate("nightly", true)), [3, 4]);
+        let entry = RepoConfig {
+            stable_chat_id: Some(config::ChatIds::One(2)),
+            ..entry
+        };
+        assert_eq!(chats(&entry, &candidate("v1.0.0", false)), [2]);
+    }
+
+    #[tokio::test]
+    async fn corrupt_json_state_exits_with_state_code() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        std::fs::write(&path, "garbage").unwrap();
+        let args = test_args(&[
+            "--once",
+            "--stdout",
+            "--skip-startup-check",
+            "--state-path",
+            path.to_str().unwrap(),
+        ]);
+        let err = run(args).await.expect_err("a corrupt state file opened");
+        assert_eq!(Exit::of(&err) as u8, 3);
+    }
+
+    #[test]
+    fn prune_orphans_keeps_watched_repos() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.db");
+        let mut store = state::open(StateBackend::Sqlite, &path, 0).unwrap();
+        let seen = |tag: &str| state::RepoState {
+            last_seen_tag: Some(tag.into()),
+            ..Default::default()
+        };
+        store.set("o/kept", &seen("v1")).unwrap();
+        store.set("o/gone", &seen("v2")).unwrap();
+        prune_orphans(store.as_mut(), &[RepoConfig::new("o/kept"), RepoConfig::new("o/new")])
+            .unwrap();
+        let left: Vec<_> = store.list().unwrap().into_iter().map(|(repo, _)| repo).collect();
+        assert_eq!(left, ["o/kept"]);
+    }
+
+    #[test]
+    fn parse_repos_file_skips_comments_and_blank_lines() {
+        let list = "# watched repos\nrust-lang/rust\n\n  octocat/Hello-World  # demo\no/r#ci.yml\n";
+        assert_eq!(parse_repos_file(list), ["rust-lang/rust", "octocat/Hello-World", "o/r#ci.yml"]);
+    }
+
+    #[test]
+    fn repo_muted_matches_wildcard_patterns() {
+        let patterns = ["noisy/*".to_string(), "o/*-bot".into(), "Exact/Repo".into()];
+        assert!(repo_muted("noisy/anything", &patterns));
+        assert!(repo_muted("o/release-bot@main", &patterns));
+        assert!(repo_muted("exact/repo#ci.yml", &patterns));
+        assert!(!repo_muted("o/bot", &patterns));
+        assert!(!repo_muted("exact/repo2", &patterns));
+        assert!(glob_match("a*b*c", "abc"));
+        assert!(!glob_match("a*bc", "abcx"));
+    }
+
+    #[test]
+    fn valid_repo_spec_requires_owner_and_repo() {
+        assert!(valid_repo_spec("rust-lang/rust"));
+        assert!(valid_repo_spec("sysrex/*"));
+        assert!(!valid_repo_spec("justaname"));
+        assert!(!valid_repo_spec("/repo"));
+        assert!(!valid_repo_spec("owner/"));
+        assert!(!valid_repo_spec("a/b/c"));
+        assert!(!valid_repo_spec("owner/my repo"));
+        assert!(valid_repo_spec("owner/repo@release/1.x"));
+        assert!(!valid_repo_spec("owner/repo@"));
+        assert!(!valid_repo_spec("owner/*@main"));
+        assert!(valid_repo_spec("owner/repo#release.yml"));
+        assert!(!valid_repo_spec("owner/repo#"));
+        assert!(valid_repo_spec("git+https://gitlab.com/foo/bar.git"));
+        assert!(valid_repo_spec("git+ssh://git@gitea.example.com/foo/bar.git"));
+        assert!(!valid_repo_spec("git+"));
+        assert!(valid_repo_spec("docker://nginx"));
+        assert!(valid_repo_spec("docker://localhost:5000/team/app"));
+        assert!(!valid_repo_spec("docker://nginx:1.25"));
+        assert!(!valid_repo_spec("docker://library/*"));
+    }
+}
@@ -0,0 +1,114 @@
+use anyhow::{Context, Result};
+use axum::{http::header, routing::get, Router};
+use prometheus::{
+    core::Collector, Encoder, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use std::sync::LazyLock;
+
+/// Process-wide metrics; always recorded, only exposed with `--metrics-port`.
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+pub struct Metrics {
+    registry: Registry,
+    /// labels: repo, result ("ok" | "error")
+    pub repo_checks: IntCounterVec,
+    /// labels: target ("telegram" | "discord" | ...)
+    pub notifications_sent: IntCounterVec,
+    pub github_api_errors: IntCounter,
+    /// labels: resource ("core" | "graphql" | ...), from the last response
+    pub github_rate_limit_remaining: IntGaugeVec,
+    /// labels: resource
+    pub github_rate_limit_limit: IntGaugeVec,
+    /// Unix time of the last pass in which at least one repo check succeeded
+    pub last_successful_poll: IntGauge,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let repo_checks = IntCounterVec::new(
+            Opts::new("repo_checks_total", "Repo checks by outcome"),
+            &["repo", "result"],
+        )
+        .expect("valid metric");
+        let notifications_sent = IntCounterVec::new(
+            Opts::new("notifications_sent_total", "Notifications delivered by target"),
+            &["target"],
+        )
+        .expect("valid metric");
+        let github_api_errors =
+            IntCounter::new("github_api_errors_total", "Failed GitHub API requests")
+                .expect("valid metric");
+        let github_rate_limit_remaining = IntGaugeVec::new(
+            Opts::new("github_rate_limit_remaining", "GitHub API requests left in the window"),
+            &["resource"],
+        )
+        .expect("valid metric");
+        let github_rate_limit_limit = IntGaugeVec::new(
+            Opts::new("github_rate_limit_limit", "GitHub API requests allowed per window"),
+            &["resource"],
+        )
+        .expect("valid metric");
+        let last_successful_poll = IntGauge::new(
+            "last_successful_poll_timestamp",
+            "Unix time of the last pass with at least one successful repo check",
+        )
+        .expect("valid metric");
+
+        let registry = Registry::new();
+        registry.register(Box::new(repo_checks.clone())).expect("unique metric");
+        registry.register(Box::new(notifications_sent.clone())).expect("unique metric");
+        registry.register(Box::new(github_api_errors.clone())).expect("unique metric");
+        registry
+            .register(Box::new(github_rate_limit_remaining.clone()))
+            .expect("unique metric");
+        registry.register(Box::new(github_rate_limit_limit.clone())).expect("unique metric");
+        registry.register(Box::new(last_successful_poll.clone())).expect("unique metric");
+
+        Self {
+            registry,
+            repo_checks,
+            notifications_sent,
+            github_api_errors,
+            github_rate_limit_remaining,
+            github_rate_limit_limit,
+            last_successful_poll,
+        }
+    }
+
+    /// Notifications delivered so far, over all targets.
+    pub fn notifications_total(&self) -> u64 {
+        self.notifications_sent
+            .collect()
+            .iter()
+            .flat_map(|family| family.get_metric())
+            .map(|m| m.get_counter().get_value() as u64)
+            .sum()
+    }
+
+    /// Renders all metrics in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let mut buf = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buf)
+            .expect("encoding metrics into a Vec");
+        String::from_utf8(buf).expect("prometheus text is utf-8")
+    }
+}
+
+/// Serves `/metrics` on `port` until the process exits.
+pub async fn serve(port: u16) -> Result<()> {
+    let app = Router::new().route(
+        "/metrics",
+        get(|| async {
+            (
+                [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+                METRICS.render(),
+            )
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind(("0.0.0.0", port))
+        .await
+        .with_context(|| format!("binding metrics port {}", port))?;
+    axum::serve(listener, app).await.context("metrics server")?;
+    Ok(())
+}
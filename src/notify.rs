@@ -0,0 +1,904 @@
+use anyhow::{Context, Result};
+use lettre::{
+    message::Mailbox, transport::smtp::authentication::Credentials, AsyncSmtpTransport,
+    AsyncTransport, Message, Tokio1Executor,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    io::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, OnceLock,
+    },
+    time::Duration,
+};
+use tokio::time::{sleep, Instant};
+use tracing::{debug, info, warn};
+
+/// Telegram rejects `sendMessage` text longer than this.
+const TELEGRAM_MAX_CHARS: usize = 4096;
+
+/// Discord rejects webhook messages with more `content` than this.
+const DISCORD_MAX_CHARS: usize = 2000;
+
+/// Shared by every [`Notifier::send`], set once by [`limit_rate`].
+static RATE_LIMITER: OnceLock<RateLimiter> = OnceLock::new();
+
+/// Makes every send wait for a token of a bucket refilled at `per_sec`
+/// (holding at most one second's worth), so a burst of detections doesn't
+/// trip the targets' own limits. 0 leaves sends unlimited.
+pub fn limit_rate(per_sec: f64) {
+    if per_sec > 0.0 {
+        let _ = RATE_LIMITER.set(RateLimiter::new(per_sec));
+    }
+}
+
+/// A token bucket that hands out tokens ahead of time: a send that finds it
+/// empty takes the next token anyway and sleeps until it is due.
+struct RateLimiter {
+    per_sec: f64,
+    capacity: f64,
+    /// Tokens left (negative when reserved ahead) as of the instant
+    bucket: Mutex<(f64, Instant)>,
+}
+
+impl RateLimiter {
+    fn new(per_sec: f64) -> Self {
+        let capacity = per_sec.max(1.0);
+        Self {
+            per_sec,
+            capacity,
+            bucket: Mutex::new((capacity, Instant::now())),
+        }
+    }
+
+    /// Takes a token at `now`, returning how long to wait before using it.
+    fn reserve(&self, now: Instant) -> Duration {
+        let mut bucket = self.bucket.lock().expect("rate limiter lock poisoned");
+        let (tokens, at) = *bucket;
+        let refilled = now.saturating_duration_since(at).as_secs_f64() * self.per_sec;
+        let tokens = (tokens + refilled).min(self.capacity) - 1.0;
+        *bucket = (tokens, now.max(at));
+        if tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-tokens / self.per_sec)
+        }
+    }
+}
+
+/// How Telegram should interpret message text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum TgParseMode {
+    #[value(name = "markdown_v2")]
+    MarkdownV2,
+    Html,
+    /// No `parse_mode`; the text is shown verbatim
+    Plain,
+}
+
+impl TgParseMode {
+    /// Value for the Bot API `parse_mode` field, if any.
+    fn api_name(self) -> Option<&'static str> {
+        match self {
+            TgParseMode::MarkdownV2 => Some("MarkdownV2"),
+            TgParseMode::Html => Some("HTML"),
+            TgParseMode::Plain => None,
+        }
+    }
+}
+
+/// Bot-wide Telegram settings, shared by every chat it sends to.
+#[derive(Debug, Clone)]
+pub struct TelegramConfig {
+    pub bot_token: String,
+    pub parse_mode: TgParseMode,
+    /// Retries per request on 429 / 5xx before giving up
+    pub max_retries: u32,
+    /// Suppress the preview card Telegram renders for the GitHub link
+    pub no_link_preview: bool,
+    /// Deliver messages without a notification sound
+    pub silent: bool,
+}
+
+/// SMTP server and addresses for email notifications.
+#[derive(Debug, Clone)]
+pub struct EmailConfig {
+    pub host: String,
+    /// 465 means implicit TLS, anything else STARTTLS
+    pub port: u16,
+    pub user: Option<String>,
+    pub pass: Option<String>,
+    pub from: String,
+    pub to: Vec<String>,
+}
+
+/// Homeserver, access token and room for Matrix notifications.
+#[derive(Debug, Clone)]
+pub struct MatrixConfig {
+    /// Base URL, e.g. https://matrix.example.com
+    pub homeserver: String,
+    pub token: String,
+    /// Room id (`!abc:example.com`) or alias (`#releases:example.com`)
+    pub room: String,
+}
+
+/// What an ntfy (or desktop) notification is made of; `send` gets it as JSON.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NtfyMessage {
+    pub title: String,
+    pub message: String,
+    /// Opened when the notification is tapped (none if empty)
+    pub click: String,
+}
+
+/// A link shown as a button under a Telegram message.
+#[derive(Debug, Clone)]
+pub struct LinkButton {
+    pub text: String,
+    pub url: String,
+}
+
+/// A single destination for notifications.
+#[derive(Debug, Clone)]
+pub enum Notifier {
+    Telegram {
+        config: TelegramConfig,
+        chat_id: i64,
+        /// Forum topic to post in, instead of the group's General topic
+        thread_id: Option<i64>,
+    },
+    Discord { webhook_url: String },
+    Slack { webhook_url: String },
+    Email { config: EmailConfig },
+    Matrix { config: MatrixConfig },
+    Teams { webhook_url: String },
+    Ntfy { server: String, topic: String },
+    /// One line per detection on stdout, for piping into scripts
+    Stdout,
+    Webhook { url: String, timeout: Duration },
+    /// A notification on the desktop the watcher runs on
+    #[cfg(feature = "desktop")]
+    Desktop,
+}
+
+impl Notifier {
+    /// Short target name, used as a metrics label.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Notifier::Telegram { .. } => "telegram",
+            Notifier::Discord { .. } => "discord",
+            Notifier::Slack { .. } => "slack",
+            Notifier::Email { .. } => "email",
+            Notifier::Matrix { .. } => "matrix",
+            Notifier::Teams { .. } => "teams",
+            Notifier::Ntfy { .. } => "ntfy",
+            Notifier::Stdout => "stdout",
+            Notifier::Webhook { .. } => "webhook",
+            #[cfg(feature = "desktop")]
+            Notifier::Desktop => "desktop",
+        }
+    }
+
+    /// Whether this target's text is an [`NtfyMessage`] as JSON.
+    pub fn takes_ntfy_message(&self) -> bool {
+        match self {
+            Notifier::Ntfy { .. } => true,
+            #[cfg(feature = "desktop")]
+            Notifier::Desktop => true,
+            _ => false,
+        }
+    }
+
+    /// Sends `text`, which must already be formatted for this target.
+    /// `buttons` are only shown by Telegram; other targets ignore them.
+    pub async fn send(
+        &self,
+        client: &reqwest::Client,
+        text: String,
+        buttons: &[LinkButton],
+        dry_run: bool,
+    ) -> Result<()> {
+        if dry_run {
+            info!(target = %self, %text, buttons = buttons.len(), "[dry-run] would send");
+            return Ok(());
+        }
+        if let Some(limiter) = RATE_LIMITER.get() {
+            let wait = limiter.reserve(Instant::now());
+            if !wait.is_zero() {
+                debug!(target = %self, ?wait, "notification rate limited");
+                sleep(wait).await;
+            }
+        }
+        match self {
+            Notifier::Telegram {
+                config,
+                chat_id,
+                thread_id,
+            } => notify_telegram(client, config, *chat_id, *thread_id, text, buttons).await,
+            Notifier::Discord { webhook_url } => notify_discord(client, webhook_url, text).await,
+            Notifier::Slack { webhook_url } => notify_slack(client, webhook_url, text).await,
+            Notifier::Email { config } => notify_email(config, text).await,
+            Notifier::Matrix { config } => notify_matrix(client, config, text).await,
+            Notifier::Teams { webhook_url } => notify_teams(client, webhook_url, text).await,
+            Notifier::Ntfy { server, topic } => notify_ntfy(client, server, topic, text).await,
+            Notifier::Stdout => notify_stdout(&text),
+            Notifier::Webhook { url, timeout } => {
+                notify_webhook(client, url, text, *timeout).await
+            }
+            #[cfg(feature = "desktop")]
+            Notifier::Desktop => notify_desktop(text).await,
+        }
+    }
+}
+
+impl fmt::Display for Notifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Notifier::Telegram {
+                chat_id,
+                thread_id: Some(thread_id),
+                ..
+            } => write!(f, "chat_id={} thread_id={}", chat_id, thread_id),
+            Notifier::Telegram { chat_id, .. } => write!(f, "chat_id={}", chat_id),
+            Notifier::Discord { .. } => write!(f, "discord"),
+            Notifier::Slack { .. } => write!(f, "slack"),
+            Notifier::Email { config } => write!(f, "email={}", config.to.join(",")),
+            Notifier::Matrix { config } => write!(f, "matrix={}", config.room),
+            Notifier::Teams { .. } => write!(f, "teams"),
+            Notifier::Ntfy { topic, .. } => write!(f, "ntfy={}", topic),
+            Notifier::Stdout => write!(f, "stdout"),
+            Notifier::Webhook { url, .. } => write!(f, "webhook={}", url),
+            #[cfg(feature = "desktop")]
+            Notifier::Desktop => write!(f, "desktop"),
+        }
+    }
+}
+
+async fn notify_telegram(
+    client: &reqwest::Client,
+    config: &TelegramConfig,
+    chat_id: i64,
+    thread_id: Option<i64>,
+    text: String,
+    buttons: &[LinkButton],
+) -> Result<()> {
+    // callers are expected to have escaped interpolated text for the parse mode
+    let url = format!("https://api.telegram.org/bot{}/sendMessage", config.bot_token);
+    let chunks = split_message(&text, TELEGRAM_MAX_CHARS, config.parse_mode);
+    for (i, chunk) in chunks.iter().enumerate() {
+        let mut payload = serde_json::json!({
+            "chat_id": chat_id,
+            "text": chunk,
+        });
+        if let Some(mode) = config.parse_mode.api_name() {
+            payload["parse_mode"] = mode.into();
+        }
+        if let Some(thread_id) = thread_id {
+            payload["message_thread_id"] = thread_id.into();
+        }
+        if config.no_link_preview {
+            payload["disable_web_page_preview"] = true.into();
+        }
+        if config.silent {
+            payload["disable_notification"] = true.into();
+        }
+        // the keyboard goes under the last chunk, i.e. the end of the message
+        if !buttons.is_empty() && i + 1 == chunks.len() {
+            let rows: Vec<_> = buttons
+                .iter()
+                .map(|b| serde_json::json!([{ "text": b.text, "url": b.url }]))
+                .collect();
+            payload["reply_markup"] = serde_json::json!({ "inline_keyboard": rows });
+        }
+
+        post_telegram(client, &url, &payload, config.max_retries)
+            .await
+            .map_err(|e| e.context(format!("chunk {}/{}", i + 1, chunks.len())))?;
+    }
+    Ok(())
+}
+
+/// Checks that the bot token is valid (`getMe`) and that the bot can access
+/// each of `chat_ids` (`getChat`), so a wrong setting fails at startup rather
+/// than at the first detection.
+pub async fn check_telegram(
+    client: &reqwest::Client,
+    config: &TelegramConfig,
+    chat_ids: &[i64],
+) -> Result<()> {
+    let api = format!("https://api.telegram.org/bot{}", config.bot_token);
+    post_telegram(client, &format!("{api}/getMe"), &serde_json::json!({}), config.max_retries)
+        .await
+        .context("Telegram rejected the bot token (getMe), check --tg-bot-token")?;
+    for chat_id in chat_ids {
+        let payload = serde_json::json!({ "chat_id": chat_id });
+        post_telegram(client, &format!("{api}/getChat"), &payload, config.max_retries)
+            .await
+            .with_context(|| {
+                format!("the bot can't access chat {chat_id} (getChat), is it a member?")
+            })?;
+    }
+    Ok(())
+}
+
+/// POSTs one Bot API request, retrying 429s after the `retry_after` Telegram
+/// asks for and 5xx with exponential backoff.
+async fn post_telegram(
+    client: &reqwest::Client,
+    url: &str,
+    payload: &serde_json::Value,
+    max_retries: u32,
+) -> Result<()> {
+    let mut attempt = 0;
+    loop {
+        let resp = client.post(url).json(payload).send().await?;
+        let status = resp.status();
+        if status.is_success() {
+            return Ok(());
+        }
+        let body = resp.text().await.unwrap_or_default();
+
+        let backoff = Duration::from_secs(1 << attempt.min(6));
+        let retry_in = if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            Some(telegram_retry_after(&body).unwrap_or(backoff))
+        } else if status.is_server_error() {
+            Some(backoff)
+        } else {
+            None
+        };
+        match retry_in {
+            Some(wait) if attempt < max_retries => {
+                attempt += 1;
+                warn!(%status, attempt, ?wait, "telegram send failed, retrying");
+                sleep(wait).await;
+            }
+            _ => anyhow::bail!("telegram send failed: {} body={}", status, body),
+        }
+    }
+}
+
+/// Reads `parameters.retry_after` (seconds) from a Telegram error body.
+fn telegram_retry_after(body: &str) -> Option<Duration> {
+    let body: serde_json::Value = serde_json::from_str(body).ok()?;
+    let secs = body.pointer("/parameters/retry_after")?.as_u64()?;
+    Some(Duration::from_secs(secs))
+}
+
+/// Splits `text` into chunks of at most `max` chars, breaking on the last
+/// newline (or else space) that fits, and hard-splitting only as a last resort.
+/// Every chunk must parse on its own in `mode`: no chunk ends inside a
+/// MarkdownV2 `\` escape or an HTML tag or entity, and a cut inside bold,
+/// code, a link etc. moves to before it. One that starts the chunk and
+/// doesn't fit is closed at the end of the chunk and reopened in the next.
+fn split_message(text: &str, max: usize, mode: TgParseMode) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut rest = text.to_string();
+    while let Some((limit, _)) = rest.char_indices().nth(max) {
+        let (mut cut, mut skip) = break_in(whole_entities(&rest[..limit], mode));
+        let mut open = open_entities(&rest[..cut], mode);
+        while let Some(start) = open.first().map(|e| e.start).filter(|&start| start > 0) {
+            (cut, skip) = break_in(&rest[..start]);
+            open = open_entities(&rest[..cut], mode);
+        }
+        let mut closers = closers_of(&open);
+        let mut room = max;
+        while closers.as_ref().is_some_and(|c| rest[..cut].chars().count() + c.len() > max) {
+            room = room.saturating_sub(closers.as_ref().map_or(0, String::len));
+            let limit = rest.char_indices().nth(room).map_or(rest.len(), |(i, _)| i);
+            (cut, skip) = break_in(whole_entities(&rest[..limit], mode));
+            open = open_entities(&rest[..cut], mode);
+            closers = closers_of(&open);
+        }
+        let openers: String = open.iter().map(|e| e.opener.as_str()).collect();
+        match closers.filter(|_| cut > openers.len()) {
+            Some(closers) => {
+                chunks.push(format!("{}{closers}", &rest[..cut]));
+                rest = format!("{openers}{}", &rest[cut + skip..]);
+            }
+            // only links are left open, or nothing would move on: a plain cut
+            None => {
+                let (cut, skip) = break_in(whole_entities(&rest[..limit], mode));
+                chunks.push(rest[..cut].to_string());
+                rest.drain(..cut + skip);
+            }
+        }
+    }
+    if !rest.is_empty() || chunks.is_empty() {
+        chunks.push(rest);
+    }
+    chunks
+}
+
+/// Where to end a chunk taken from the start of `window`: its last newline,
+/// else space (dropped, so `1` to skip), else all of it.
+fn break_in(window: &str) -> (usize, usize) {
+    match window.rfind('\n').or_else(|| window.rfind(' ')).filter(|&i| i > 0) {
+        Some(i) => (i, 1),
+        None => (window.len(), 0),
+    }
+}
+
+/// Markup still open at the end of a chunk.
+struct Entity {
+    /// Byte offset of the opener in the chunk
+    start: usize,
+    /// The opener as written, to reopen it in the next chunk
+    opener: String,
+    /// `None` for a link, which can't be closed without its URL
+    closer: Option<String>,
+}
+
+/// What closes `open`, innermost first, unless a link is among them.
+fn closers_of(open: &[Entity]) -> Option<String> {
+    open.iter().rev().map(|e| e.closer.as_deref()).collect()
+}
+
+/// The entities of `mode` opened in `text` and not closed by its end,
+/// outermost first.
+fn open_entities(text: &str, mode: TgParseMode) -> Vec<Entity> {
+    match mode {
+        TgParseMode::MarkdownV2 => open_markdown(text),
+        TgParseMode::Html => open_html(text),
+        TgParseMode::Plain => Vec::new(),
+    }
+}
+
+fn open_html(text: &str) -> Vec<Entity> {
+    let mut open: Vec<Entity> = Vec::new();
+    let mut from = 0;
+    while let Some(at) = text[from..].find('<').map(|i| from + i) {
+        let Some(end) = text[at..].find('>').map(|i| at + i + 1) else {
+            break;
+        };
+        let tag = &text[at..end];
+        let inner = &tag[1..tag.len() - 1];
+        if let Some(name) = inner.strip_prefix('/') {
+            let closer = format!("</{}>", name.trim());
+            if let Some(pos) = open.iter().rposition(|e| e.closer.as_ref() == Some(&closer)) {
+                open.truncate(pos);
+            }
+        } else {
+            let name = inner.split_whitespace().next().unwrap_or_default();
+            open.push(Entity {
+                start: at,
+                opener: tag.to_string(),
+                closer: Some(format!("</{name}>")),
+            });
+        }
+        from = end;
+    }
+    open
+}
+
+fn open_markdown(text: &str) -> Vec<Entity> {
+    let mut open: Vec<Entity> = Vec::new();
+    let toggle = |open: &mut Vec<Entity>, start: usize, marker: &str| {
+        match open.iter().rposition(|e| e.opener == marker) {
+            Some(pos) => open.truncate(pos),
+            None => open.push(Entity {
+                start,
+                opener: marker.to_string(),
+                closer: Some(marker.to_string()),
+            }),
+        }
+    };
+    let bytes = text.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        // inside code only its closing backticks count
+        let code = open.last().and_then(|e| e.closer.clone()).filter(|c| c.starts_with('`'));
+        let rest = &text[i..];
+        let char_len = |at: usize| rest[at..].chars().next().map_or(1, char::len_utf8);
+        let step = match (bytes[i], code.as_deref()) {
+            (b'\\', _) => 1 + char_len(1),
+            (b'`', Some(closer)) => {
+                if rest.starts_with(closer) {
+                    open.pop();
+                    closer.len()
+                } else {
+                    1
+                }
+            }
+            (_, Some(_)) => char_len(0),
+            (b'`', None) if rest.starts_with("```") => {
+                // reopened with its language line
+                let opener = match rest.find('\n') {
+                    Some(nl) => &rest[..=nl],
+                    None => "```",
+                };
+                open.push(Entity {
+                    start: i,
+                    opener: opener.to_string(),
+                    closer: Some("```".into()),
+                });
+                opener.len()
+            }
+            (b'`', None) => {
+                toggle(&mut open, i, "`");
+                1
+            }
+            (b'_', None) if rest.starts_with("__") => {
+                toggle(&mut open, i, "__");
+                2
+            }
+            (b'|', None) if rest.starts_with("||") => {
+                toggle(&mut open, i, "||");
+                2
+            }
+            (b'*' | b'_' | b'~', None) => {
+                toggle(&mut open, i, &rest[..1]);
+                1
+            }
+            (b'[', None) => {
+                open.push(Entity {
+                    start: i,
+                    opener: "[".into(),
+                    closer: None,
+                });
+                1
+            }
+            (b']', None) if rest.starts_with("](") => {
+                let Some(url_end) = link_url_end(&rest[2..]) else {
+                    // the URL goes on in the next chunk: the link is still open
+                    break;
+                };
+                if let Some(pos) = open.iter().rposition(|e| e.closer.is_none()) {
+                    open.truncate(pos);
+                }
+                2 + url_end
+            }
+            _ => char_len(0),
+        };
+        i += step;
+    }
+    open
+}
+
+/// Bytes up to and including the `)` ending a link's URL, which may hold
+/// `\)` escapes.
+fn link_url_end(url: &str) -> Option<usize> {
+    let bytes = url.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 2,
+            b')' => return Some(i + 1),
+            _ => i += 1,
+        }
+    }
+    None
+}
+
+/// `window` up to before a trailing markup of `mode` it cuts through: an
+/// unpaired `\` for MarkdownV2, an unclosed `<` or `&` for HTML. Left whole
+/// when that would leave nothing.
+fn whole_entities(window: &str, mode: TgParseMode) -> &str {
+    let cut = match mode {
+        TgParseMode::MarkdownV2 => {
+            let escapes = window.len() - window.trim_end_matches('\\').len();
+            (escapes % 2 == 1).then(|| window.len() - 1)
+        }
+        TgParseMode::Html => {
+            let open = |start: char, end: char| {
+                window.rfind(start).filter(|&i| !window[i..].contains(end))
+            };
+            open('<', '>').into_iter().chain(open('&', ';')).min()
+        }
+        TgParseMode::Plain => None,
+    };
+    match cut {
+        Some(cut) if cut > 0 => &window[..cut],
+        _ => window,
+    }
+}
+
+async fn notify_discord(
+    client: &reqwest::Client,
+    webhook_url: &str,
+    content: String,
+) -> Result<()> {
+    let payload = serde_json::json!({
+        "content": crate::truncate_chars(&content, DISCORD_MAX_CHARS),
+    });
+
+    let resp = client.post(webhook_url).json(&payload).send().await?;
+    let status = resp.status();
+    if !status.is_success() {
+        let body = resp.text().await.unwrap_or_default();
+        anyhow::bail!("discord send failed: {} body={}", status, body);
+    }
+    Ok(())
+}
+
+async fn notify_slack(client: &reqwest::Client, webhook_url: &str, text: String) -> Result<()> {
+    let payload = serde_json::json!({ "text": text });
+
+    let resp = client.post(webhook_url).json(&payload).send().await?;
+    let status = resp.status();
+    let body = resp.text().await.unwrap_or_default();
+    // Slack can answer 200 with an error like `invalid_payload` in the body
+    if !status.is_success() || body.trim() != "ok" {
+        anyhow::bail!("slack send failed: {} body={}", status, body);
+    }
+    Ok(())
+}
+
+/// Sends `text` as an `m.text` message, with an HTML `formatted_body` in
+/// which its URLs are links.
+async fn notify_matrix(
+    client: &reqwest::Client,
+    config: &MatrixConfig,
+    text: String,
+) -> Result<()> {
+    // Matrix drops a second event with the same transaction id, so every send
+    // (retries included) needs a fresh one
+    static TXN_COUNTER: AtomicU64 = AtomicU64::new(0);
+    let txn_id = format!(
+        "dockmasterbot-{}-{}",
+        chrono::Utc::now().timestamp_millis(),
+        TXN_COUNTER.fetch_add(1, Ordering::Relaxed)
+    );
+    let mut url = reqwest::Url::parse(&config.homeserver).context("invalid --matrix-homeserver")?;
+    url.path_segments_mut()
+        .map_err(|_| anyhow::anyhow!("invalid --matrix-homeserver"))?
+        .pop_if_empty()
+        .extend(["_matrix", "client", "v3", "rooms", &config.room])
+        .extend(["send", "m.room.message", &txn_id]);
+    let payload = serde_json::json!({
+        "msgtype": "m.text",
+        "body": text,
+        "format": "org.matrix.custom.html",
+        "formatted_body": matrix_html(&text),
+    });
+
+    let resp = client.put(url).bearer_auth(&config.token).json(&payload).send().await?;
+    let status = resp.status();
+    if !status.is_success() {
+        let body = resp.text().await.unwrap_or_default();
+        anyhow::bail!("matrix send failed: {} body={}", status, body);
+    }
+    Ok(())
+}
+
+/// `text` as HTML: escaped, with line breaks kept and URLs turned into links.
+fn matrix_html(text: &str) -> String {
+    let lines: Vec<String> = text
+        .lines()
+        .map(|line| {
+            line.split(' ')
+                .map(|word| {
+                    let escaped = word
+                        .replace('&', "&amp;")
+                        .replace('<', "&lt;")
+                        .replace('>', "&gt;")
+                        .replace('"', "&quot;");
+                    if word.starts_with("https://") || word.starts_with("http://") {
+                        format!("<a href=\"{escaped}\">{escaped}</a>")
+                    } else {
+                        escaped
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .collect();
+    lines.join("<br>")
+}
+
+/// POSTs `card`, a MessageCard JSON document, to a Teams incoming webhook.
+async fn notify_teams(client: &reqwest::Client, webhook_url: &str, card: String) -> Result<()> {
+    let resp = client
+        .post(webhook_url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(card)
+        .send()
+        .await?;
+    let status = resp.status();
+    let body = resp.text().await.unwrap_or_default();
+    // Teams answers `1` on success and an error message otherwise, not always
+    // with an error status
+    if !status.is_success() || body.trim() != "1" {
+        anyhow::bail!("teams send failed: {} body={}", status, body);
+    }
+    Ok(())
+}
+
+/// Publishes `message` (an [`NtfyMessage`] as JSON) to `topic`, with the
+/// title and click URL in headers.
+async fn notify_ntfy(
+    client: &reqwest::Client,
+    server: &str,
+    topic: &str,
+    message: String,
+) -> Result<()> {
+    let message: NtfyMessage = serde_json::from_str(&message).context("invalid ntfy message")?;
+    let url = format!("{}/{}", server.trim_end_matches('/'), topic);
+    let mut request = client
+        .post(url)
+        .header("Title", &message.title)
+        .header("Tags", "rocket");
+    if !message.click.is_empty() {
+        request = request.header("Click", &message.click);
+    }
+    let resp = request.body(message.message).send().await?;
+    let status = resp.status();
+    if !status.is_success() {
+        let body = resp.text().await.unwrap_or_default();
+        anyhow::bail!("ntfy send failed: {} body={}", status, body);
+    }
+    Ok(())
+}
+
+/// Shows `message` (an [`NtfyMessage`] as JSON) as a desktop notification,
+/// with an "Open" action that opens the click URL in the browser. Clicking
+/// may come any time later, so the action is waited for in the background.
+#[cfg(feature = "desktop")]
+async fn notify_desktop(message: String) -> Result<()> {
+    let message: NtfyMessage = serde_json::from_str(&message).context("invalid desktop message")?;
+    let mut toast = notify_rust::Notification::new();
+    toast.appname(env!("CARGO_PKG_NAME")).summary(&message.title).body(&message.message);
+    if !message.click.is_empty() {
+        toast.action("default", "Open");
+    }
+    // showing talks to the notification server synchronously
+    let handle = tokio::task::spawn_blocking(move || toast.show())
+        .await
+        .context("desktop notification task")?
+        .context("showing the desktop notification")?;
+    if !message.click.is_empty() {
+        tokio::task::spawn_blocking(move || {
+            handle.wait_for_action(|action| {
+                if action != "default" {
+                    return;
+                }
+                if let Err(e) = open::that(&message.click) {
+                    warn!(url = %message.click, error=?e, "opening the notification's URL failed");
+                }
+            })
+        });
+    }
+    Ok(())
+}
+
+/// Prints `line` and flushes, so a reading pipe sees it right away.
+fn notify_stdout(line: &str) -> Result<()> {
+    let mut stdout = std::io::stdout().lock();
+    writeln!(stdout, "{line}").context("writing to stdout")?;
+    stdout.flush().context("flushing stdout")?;
+    Ok(())
+}
+
+/// Mails `text` to every recipient; its first line is used as the subject.
+async fn notify_email(config: &EmailConfig, text: String) -> Result<()> {
+    let (subject, body) = text.split_once('\n').unwrap_or((&text, ""));
+    let mut message = Message::builder()
+        .from(config.from.parse::<Mailbox>().context("invalid --email-from")?)
+        .subject(subject);
+    for to in &config.to {
+        let to = to
+            .parse::<Mailbox>()
+            .with_context(|| format!("invalid --email-to {to}"))?;
+        message = message.to(to);
+    }
+    let message = message.body(body.trim_start().to_string())?;
+
+    let mut transport = if config.port == 465 {
+        AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host)?
+    } else {
+        AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host)?
+    }
+    .port(config.port);
+    if let (Some(user), Some(pass)) = (&config.user, &config.pass) {
+        transport = transport.credentials(Credentials::new(user.clone(), pass.clone()));
+    }
+    transport.build().send(message).await.map_err(|e| {
+        // 535 is the SMTP reply for rejected credentials
+        if e.status().is_some_and(|code| code.to_string() == "535") {
+            anyhow::anyhow!("SMTP authentication failed for {}: {}", config.host, e)
+        } else {
+            anyhow::anyhow!("email send via {}:{} failed: {}", config.host, config.port, e)
+        }
+    })?;
+    Ok(())
+}
+
+/// POSTs `event`, a pre-serialized JSON document, to a generic webhook.
+async fn notify_webhook(
+    client: &reqwest::Client,
+    url: &str,
+    event: String,
+    timeout: Duration,
+) -> Result<()> {
+    let resp = client
+        .post(url)
+        .timeout(timeout)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(event)
+        .send()
+        .await?;
+    let status = resp.status();
+    if !status.is_success() {
+        let body = resp.text().await.unwrap_or_default();
+        anyhow::bail!("webhook send failed: {} body={}", status, body);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_limiter_queues_sends_over_the_burst() {
+        let limiter = RateLimiter::new(2.0);
+        let start = Instant::now();
+        assert_eq!(limiter.reserve(start), Duration::ZERO);
+        assert_eq!(limiter.reserve(start), Duration::ZERO);
+        assert_eq!(limiter.reserve(start), Duration::from_millis(500));
+        assert_eq!(limiter.reserve(start), Duration::from_secs(1));
+        // tokens come back at the configured rate, never above the burst
+        assert_eq!(limiter.reserve(start + Duration::from_secs(60)), Duration::ZERO);
+        assert_eq!(limiter.reserve(start + Duration::from_secs(60)), Duration::ZERO);
+        assert!(!limiter.reserve(start + Duration::from_secs(60)).is_zero());
+    }
+
+    #[test]
+    fn split_message_prefers_newlines() {
+        let plain = TgParseMode::Plain;
+        assert_eq!(split_message("short", 10, plain), vec!["short"]);
+        assert_eq!(split_message("aaaa\nbbbb\ncc", 10, plain), vec!["aaaa\nbbbb", "cc"]);
+        assert_eq!(split_message("aaa bbb ccc", 8, plain), vec!["aaa bbb", "ccc"]);
+        assert_eq!(split_message("abcdefghij", 4, plain), vec!["abcd", "efgh", "ij"]);
+        // counts chars, not bytes
+        assert_eq!(split_message("ééééé", 4, plain), vec!["éééé", "é"]);
+    }
+
+    #[test]
+    fn split_message_keeps_escapes_and_entities_whole() {
+        let markdown = TgParseMode::MarkdownV2;
+        assert_eq!(split_message("abc\\.def", 4, markdown), vec!["abc", "\\.de", "f"]);
+        assert_eq!(split_message("ab\\\\cdef", 4, markdown), vec!["ab\\\\", "cdef"]);
+        assert_eq!(split_message("abc\\.def", 4, TgParseMode::Plain), vec!["abc\\", ".def"]);
+        let html = TgParseMode::Html;
+        assert_eq!(split_message("ab&amp;cd", 6, html), vec!["ab", "&amp;c", "d"]);
+        assert_eq!(split_message("xy<b>c d</b>", 9, html), vec!["xy", "<b>c</b>", "<b>d</b>"]);
+        assert_eq!(
+            split_message("ab <b>cdefghijkl</b>", 12, html),
+            vec!["ab", "<b>cdefg</b>", "<b>hijkl</b>"]
+        );
+        assert_eq!(split_message("ab *cdefghijkl*", 8, markdown), vec!["ab", "*cdefgh*", "*ijkl*"]);
+        assert_eq!(
+            split_message("```rust\nlet x = 1;\nlet y = 2;\n```", 24, markdown),
+            vec!["```rust\nlet x = 1;```", "```rust\nlet y = 2;\n```"]
+        );
+        // a link moves to the next chunk whole
+        assert_eq!(
+            split_message("see [notes](https://x.y/z) now", 24, markdown),
+            vec!["see", "[notes](https://x.y/z)", "now"]
+        );
+        let text = "*bold \\* text* and `code` and _more \\_ text_ ".repeat(20);
+        for chunk in split_message(&text, 30, markdown) {
+            assert!(chunk.chars().count() <= 30, "{chunk:?}");
+            assert!(open_entities(&chunk, markdown).is_empty(), "{chunk:?}");
+        }
+    }
+
+    #[test]
+    fn matrix_html_links_urls() {
+        assert_eq!(
+            matrix_html("New tag in o/r: <v1>\nhttps://github.com/o/r?a=1&b=2"),
+            "New tag in o/r: &lt;v1&gt;<br>\
+             <a href=\"https://github.com/o/r?a=1&amp;b=2\">https://github.com/o/r?a=1&amp;b=2</a>"
+        );
+    }
+
+    #[test]
+    fn telegram_retry_after_reads_parameters() {
+        let body = r#"{"ok":false,"error_code":429,"parameters":{"retry_after":7}}"#;
+        assert_eq!(telegram_retry_after(body), Some(Duration::from_secs(7)));
+        assert_eq!(telegram_retry_after(r#"{"ok":false}"#), None);
+        assert_eq!(telegram_retry_after("not json"), None);
+    }
+}
@@ -0,0 +1,202 @@
+use anyhow::{Context, Result};
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    routing::post,
+    Router,
+};
+use octocrab::models;
+use ring::hmac;
+use serde::Deserialize;
+use std::{net::SocketAddr, sync::Arc};
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    author_filtered_out, config::RepoConfig, ignore_patterns, notifiers_for, notify_all,
+    release_candidate, release_wanted, repo_muted, resolve_repos, route, shutdown_signal,
+    startup_check, tag_filtered_out, Args, Candidate, Exit, Kind,
+};
+
+struct Receiver {
+    args: Args,
+    client: reqwest::Client,
+    /// Configured entries; empty means every repo sending webhooks is announced
+    repos: Vec<RepoConfig>,
+    key: hmac::Key,
+}
+
+#[derive(Deserialize)]
+struct Repository {
+    full_name: String,
+}
+
+#[derive(Deserialize)]
+struct ReleaseEvent {
+    action: String,
+    release: models::repos::Release,
+    repository: Repository,
+}
+
+#[derive(Deserialize)]
+struct CreateEvent {
+    #[serde(rename = "ref")]
+    git_ref: String,
+    ref_type: String,
+    repository: Repository,
+}
+
+/// Serves `POST /webhook` on `addr` until shut down, announcing published
+/// releases and created tags through the same notifiers and message format
+/// as the poll loop. The state isn't touched.
+pub async fn serve(
+    args: &Args,
+    client: reqwest::Client,
+    addr: SocketAddr,
+    secret: &str,
+) -> Result<()> {
+    let repos = resolve_repos(args).context(Exit::Config)?;
+    startup_check(&repos, &client, args).await?;
+    let receiver = Receiver {
+        args: args.clone(),
+        client,
+        repos,
+        key: hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes()),
+    };
+    let app = Router::new()
+        .route("/webhook", post(webhook))
+        .with_state(Arc::new(receiver));
+    let listener = tokio::net::TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("binding webhook receiver to {}", addr))?;
+    info!(%addr, "receiving GitHub webhooks on /webhook");
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown_signal())
+        .await
+        .context("webhook receiver")?;
+    Ok(())
+}
+
+async fn webhook(
+    State(receiver): State<Arc<Receiver>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> StatusCode {
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+    if !signature_valid(&receiver.key, &body, header("x-hub-signature-256")) {
+        warn!("rejecting webhook with a missing or wrong signature");
+        return StatusCode::UNAUTHORIZED;
+    }
+    let event = header("x-github-event").unwrap_or_default();
+    let parsed = match event {
+        "ping" => return StatusCode::OK,
+        "release" => serde_json::from_slice::<ReleaseEvent>(&body).map(|e| {
+            let wanted = e.action == "published" && release_wanted(&e.release, &receiver.args);
+            wanted.then(|| (e.repository.full_name, release_candidate(e.release)))
+        }),
+        "create" => serde_json::from_slice::<CreateEvent>(&body).map(|e| {
+            let tag = e.ref_type == "tag";
+            tag.then(|| (e.repository.full_name, Candidate::new(e.git_ref, Kind::Tag)))
+        }),
+        _ => Ok(None),
+    };
+    let (repo, candidate) = match parsed {
+        Ok(Some(detection)) => detection,
+        Ok(None) => {
+            debug!(event, "ignoring webhook event");
+            return StatusCode::NO_CONTENT;
+        }
+        Err(e) => {
+            warn!(event, error=?e, "unparseable webhook payload");
+            return StatusCode::BAD_REQUEST;
+        }
+    };
+    let Some(entry) = receiver.entry_for(&repo) else {
+        debug!(%repo, "ignoring webhook for a repo that isn't configured");
+        return StatusCode::NO_CONTENT;
+    };
+    let filtered = tag_filtered_out(&candidate.tag, &entry, &receiver.args)
+        .or_else(|| author_filtered_out(&candidate, &entry))
+        .or_else(|| {
+            let muted = repo_muted(&repo, &ignore_patterns(&receiver.args));
+            muted.then_some("repo muted by --ignore-file")
+        });
+    if let Some(reason) = filtered {
+        debug!(%repo, tag = %candidate.tag, reason, "tag filtered out");
+        return StatusCode::NO_CONTENT;
+    }
+
+    // GitHub gives up on deliveries after 10s, slow targets must not count
+    tokio::spawn(async move {
+        info!(%repo, tag = %candidate.tag, "new tag received");
+        let notifiers = notifiers_for(&entry, &receiver.args);
+        let notifiers = route(&entry, &candidate, &notifiers, &receiver.args);
+        if let Err(e) = notify_all(&repo, &notifiers, &candidate, &receiver.client, &receiver.args)
+            .await {
+            error!(%repo, error=?e, "notifying about webhook event failed");
+        }
+    });
+    StatusCode::ACCEPTED
+}
+
+impl Receiver {
+    /// Settings for `repo`: its own entry, else its owner's `owner/*` one.
+    /// Branch and workflow entries don't match, those events aren't received.
+    fn entry_for(&self, repo: &str) -> Option<RepoConfig> {
+        if self.repos.is_empty() {
+            return Some(RepoConfig::new(repo));
+        }
+        let owner = repo.split('/').next().unwrap_or_default();
+        let entry = self
+            .repos
+            .iter()
+            .find(|r| r.name.eq_ignore_ascii_case(repo))
+            .or_else(|| {
+                self.repos
+                    .iter()
+                    .find(|r| r.wildcard_owner().is_some_and(|o| o.eq_ignore_ascii_case(owner)))
+            })?;
+        Some(RepoConfig {
+            name: repo.to_string(),
+            ..entry.clone()
+        })
+    }
+}
+
+/// Checks a `sha256=<hex>` X-Hub-Signature-256 header against `body`
+/// (in constant time).
+fn signature_valid(key: &hmac::Key, body: &[u8], header: Option<&str>) -> bool {
+    let Some(hex) = header.and_then(|h| h.strip_prefix("sha256=")) else {
+        return false;
+    };
+    match decode_hex(hex) {
+        Some(tag) => hmac::verify(key, body, &tag).is_ok(),
+        None => false,
+    }
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signature_valid_checks_github_example() {
+        // the example from GitHub's "Validating webhook deliveries" docs
+        let key = hmac::Key::new(hmac::HMAC_SHA256, b"It's a Secret to Everybody");
+        let good = "sha256=757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17";
+        assert!(signature_valid(&key, b"Hello, World!", Some(good)));
+        assert!(!signature_valid(&key, b"Hello, World?", Some(good)));
+        assert!(!signature_valid(&key, b"Hello, World!", Some("sha256=zz")));
+        assert!(!signature_valid(&key, b"Hello, World!", None));
+    }
+}
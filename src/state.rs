@@ -0,0 +1,686 @@
+use crate::Kind;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tracing::{error, warn};
+
+/// Where `--state-path` points to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum StateBackend {
+    /// One JSON file, rewritten after every pass
+    Json,
+    /// A SQLite database, updated row by row
+    Sqlite,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RepoState {
+    pub last_seen_tag: Option<String>,
+    pub last_checked_at: Option<DateTime<Utc>>,
+    pub last_notified_at: Option<DateTime<Utc>>,
+    /// How `last_seen_tag` was detected
+    pub kind: Option<Kind>,
+    /// Conclusion of the last seen workflow run (`last_seen_tag` holds its id)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub conclusion: Option<String>,
+    /// Failed checks in a row, reset by the next successful one
+    #[serde(default)]
+    pub consecutive_failures: u32,
+    /// Error of the last failed check, cleared by the next successful one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error_at: Option<DateTime<Utc>>,
+    /// Detections held back during quiet hours, oldest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub queued: Vec<QueuedDetection>,
+    /// Pre-release tags seen lately, oldest first, to tell their promotion to
+    /// a stable release apart
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub prereleases: Vec<String>,
+    /// The newest detection held back by --notify-cooldown-secs, sent once
+    /// the cooldown is over
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub held: Option<QueuedDetection>,
+}
+
+/// A detection whose notification waits for the end of the quiet hours.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QueuedDetection {
+    pub tag: String,
+    pub kind: Kind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit_sha: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub release_name: Option<String>,
+    #[serde(default)]
+    pub initial: bool,
+    #[serde(default)]
+    pub prerelease: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub promoted_from: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verified: Option<bool>,
+}
+
+/// Persistence for per-repo state and the ETags of the last GitHub responses.
+/// ETags are kept per `Kind`, i.e. per releases / tags endpoint.
+pub trait StateStore: Send {
+    fn get(&self, repo: &str) -> Result<Option<RepoState>>;
+    fn set(&mut self, repo: &str, state: &RepoState) -> Result<()>;
+    fn etag(&self, repo: &str, kind: Kind) -> Result<Option<String>>;
+    fn set_etag(&mut self, repo: &str, kind: Kind, etag: &str) -> Result<()>;
+    /// Forgets `repo`, ETags included so it is fetched afresh. Returns
+    /// whether anything was recorded for it.
+    fn remove(&mut self, repo: &str) -> Result<bool>;
+    /// Moves `from`'s state (etags included) to `to`; returns whether there
+    /// was any. Nothing moves if `to` already has state of its own.
+    fn rename(&mut self, from: &str, to: &str) -> Result<bool>;
+    /// Forgets every repo; returns how many there were.
+    fn clear(&mut self) -> Result<usize>;
+    /// Every repo's state, sorted by repo.
+    fn list(&self) -> Result<Vec<(String, RepoState)>>;
+    /// Whether any repo has a recorded tag yet.
+    fn has_seen_tags(&self) -> Result<bool>;
+    /// Writes out anything still buffered in memory.
+    fn flush(&mut self) -> Result<()>;
+
+    /// Read-modify-write of one repo's state, starting from the default.
+    fn update(&mut self, repo: &str, f: &mut dyn FnMut(&mut RepoState)) -> Result<()> {
+        let mut state = self.get(repo)?.unwrap_or_default();
+        f(&mut state);
+        self.set(repo, &state)
+    }
+}
+
+/// Opens the store at `path`; `backups` is how many rotated copies the JSON
+/// backend keeps (SQLite ignores it).
+pub fn open(backend: StateBackend, path: &Path, backups: usize) -> Result<Box<dyn StateStore>> {
+    Ok(match backend {
+        StateBackend::Json => Box::new(JsonStore::open(path, backups)?),
+        StateBackend::Sqlite => Box::new(SqliteStore::open(path)?),
+    })
+}
+
+/// Keeps other processes from writing the same JSON state file for as long
+/// as it lives; the lock is released on drop (and when the process exits).
+pub struct StateLock {
+    _file: fs::File,
+}
+
+/// Takes an exclusive advisory lock on `<path>.lock`, failing right away if
+/// another instance holds it. SQLite locks the database itself, so only the
+/// JSON backend, which rewrites the whole file, needs this.
+pub fn lock(backend: StateBackend, path: &Path) -> Result<Option<StateLock>> {
+    if backend == StateBackend::Sqlite {
+        return Ok(None);
+    }
+    let mut lock_path = path.as_os_str().to_owned();
+    lock_path.push(".lock");
+    let lock_path = PathBuf::from(lock_path);
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_path)
+        .with_context(|| format!("opening state lock {}", lock_path.display()))?;
+    match file.try_lock() {
+        Ok(()) => Ok(Some(StateLock { _file: file })),
+        Err(fs::TryLockError::WouldBlock) => anyhow::bail!(
+            "another instance is using the state file {} ({} is locked)",
+            path.display(),
+            lock_path.display()
+        ),
+        Err(fs::TryLockError::Error(e)) => {
+            Err(e).with_context(|| format!("locking {}", lock_path.display()))
+        }
+    }
+}
+
+const KINDS: [Kind; 4] = [Kind::Release, Kind::Tag, Kind::Commit, Kind::Workflow];
+
+fn endpoint(kind: Kind) -> &'static str {
+    match kind {
+        Kind::Release => "releases",
+        Kind::Tag => "tags",
+        Kind::Commit => "commits",
+        Kind::Workflow => "runs",
+    }
+}
+
+/// Same spelling as the serde representation used in the JSON file.
+pub fn kind_name(kind: Kind) -> &'static str {
+    match kind {
+        Kind::Release => "release",
+        Kind::Tag => "tag",
+        Kind::Commit => "commit",
+        Kind::Workflow => "workflow",
+    }
+}
+
+fn parse_kind(name: &str) -> Option<Kind> {
+    KINDS.into_iter().find(|&k| kind_name(k) == name)
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+struct JsonState {
+    /// repo -> what was last seen there and when
+    #[serde(default, alias = "last_seen", deserialize_with = "upgrade_repo_states")]
+    repos: HashMap<String, RepoState>,
+    /// "owner/repo:releases" / "owner/repo:tags" -> ETag of the last response
+    #[serde(default)]
+    etags: HashMap<String, String>,
+}
+
+/// Accepts both the current `repo -> RepoState` map and the old
+/// `last_seen: repo -> tag` one, upgrading the latter on load.
+fn upgrade_repo_states<'de, D: Deserializer<'de>>(
+    d: D,
+) -> std::result::Result<HashMap<String, RepoState>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Stored {
+        Tag(String),
+        Full(Box<RepoState>),
+    }
+    let stored = HashMap::<String, Stored>::deserialize(d)?;
+    Ok(stored
+        .into_iter()
+        .map(|(repo, s)| match s {
+            Stored::Tag(tag) => (
+                repo,
+                RepoState {
+                    last_seen_tag: Some(tag),
+                    ..RepoState::default()
+                },
+            ),
+            Stored::Full(s) => (repo, *s),
+        })
+        .collect())
+}
+
+/// Keeps everything in memory and rewrites the whole file on `flush`,
+/// optionally keeping the previous versions as `state.json.1`, `.2`, ...
+pub struct JsonStore {
+    path: PathBuf,
+    state: JsonState,
+    backups: usize,
+}
+
+impl JsonStore {
+    /// A missing file starts out empty; an unreadable one falls back to the
+    /// newest backup that still parses, and is an error if there is none.
+    pub fn open(path: &Path, backups: usize) -> Result<Self> {
+        let state = match Self::load(path) {
+            Ok(state) => state,
+            Err(e) => {
+                error!(path = %path.display(), error=?e, "state file unreadable");
+                Self::recover(path, backups).with_context(|| {
+                    format!("state file {} is corrupt and no usable backup exists", path.display())
+                })?
+            }
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            state,
+            backups,
+        })
+    }
+    fn recover(path: &Path, backups: usize) -> Option<JsonState> {
+        for n in 1..=backups {
+            let backup = backup_path(path, n);
+            if !backup.exists() {
+                break;
+            }
+            match Self::load(&backup) {
+                Ok(state) => {
+                    warn!(backup = %backup.display(), "recovered state from backup");
+                    return Some(state);
+                }
+                Err(e) => warn!(backup = %backup.display(), error=?e, "state backup unreadable"),
+            }
+        }
+        None
+    }
+    /// Shifts `.1` .. `.N-1` up by one and copies the current file to `.1`.
+    fn rotate_backups(&self) -> Result<()> {
+        if self.backups == 0 || !self.path.exists() {
+            return Ok(());
+        }
+        for n in (1..self.backups).rev() {
+            let from = backup_path(&self.path, n);
+            if from.exists() {
+                let to = backup_path(&self.path, n + 1);
+                fs::rename(&from, &to)
+                    .with_context(|| format!("rotating state backup {}", from.display()))?;
+            }
+        }
+        let first = backup_path(&self.path, 1);
+        // copy rather than move, so the state file is never missing
+        fs::copy(&self.path, &first)
+            .with_context(|| format!("writing state backup {}", first.display()))?;
+        Ok(())
+    }
+    fn load(p: &Path) -> Result<JsonState> {
+        if p.exists() {
+            let s = fs::read_to_string(p)
+                .with_context(|| format!("reading state file {}", p.display()))?;
+            serde_json::from_str(&s)
+                .with_context(|| format!("parsing state file {}", p.display()))
+        } else {
+            Ok(JsonState::default())
+        }
+    }
+}
+
+impl StateStore for JsonStore {
+    fn get(&self, repo: &str) -> Result<Option<RepoState>> {
+        Ok(self.state.repos.get(repo).cloned())
+    }
+    fn set(&mut self, repo: &str, state: &RepoState) -> Result<()> {
+        self.state.repos.insert(repo.to_string(), state.clone());
+        Ok(())
+    }
+    fn etag(&self, repo: &str, kind: Kind) -> Result<Option<String>> {
+        let key = format!("{repo}:{}", endpoint(kind));
+        Ok(self.state.etags.get(&key).cloned())
+    }
+    fn set_etag(&mut self, repo: &str, kind: Kind, etag: &str) -> Result<()> {
+        let key = format!("{repo}:{}", endpoint(kind));
+        self.state.etags.insert(key, etag.to_string());
+        Ok(())
+    }
+    fn remove(&mut self, repo: &str) -> Result<bool> {
+        let mut found = self.state.repos.remove(repo).is_some();
+        for kind in KINDS {
+            let key = format!("{repo}:{}", endpoint(kind));
+            found |= self.state.etags.remove(&key).is_some();
+        }
+        Ok(found)
+    }
+    fn rename(&mut self, from: &str, to: &str) -> Result<bool> {
+        if self.state.repos.contains_key(to) {
+            return Ok(false);
+        }
+        let Some(state) = self.state.repos.remove(from) else {
+            return Ok(false);
+        };
+        self.state.repos.insert(to.to_string(), state);
+        for kind in KINDS {
+            let key = format!("{from}:{}", endpoint(kind));
+            if let Some(etag) = self.state.etags.remove(&key) {
+                self.state.etags.insert(format!("{to}:{}", endpoint(kind)), etag);
+            }
+        }
+        Ok(true)
+    }
+    fn clear(&mut self) -> Result<usize> {
+        let count = self.state.repos.len();
+        self.state = JsonState::default();
+        Ok(count)
+    }
+    fn list(&self) -> Result<Vec<(String, RepoState)>> {
+        let mut repos: Vec<_> = self.state.repos.clone().into_iter().collect();
+        repos.sort_by(|(a, _), (b, _)| a.cmp(b));
+        Ok(repos)
+    }
+    fn has_seen_tags(&self) -> Result<bool> {
+        Ok(self.state.repos.values().any(|r| r.last_seen_tag.is_some()))
+    }
+    fn flush(&mut self) -> Result<()> {
+        let p = &self.path;
+        let bytes = serde_json::to_vec_pretty(&self.state)?;
+        // unchanged state would only push the older backups out
+        if fs::read(p).is_ok_and(|current| current == bytes) {
+            return Ok(());
+        }
+        let tmp = format!("{}.tmp", p.display());
+        fs::write(&tmp, bytes)
+            .with_context(|| format!("writing tmp state {}", tmp))?;
+        self.rotate_backups()?;
+        fs::rename(&tmp, p).with_context(|| format!("replacing state {}", p.display()))?;
+        Ok(())
+    }
+}
+
+/// `state.json.<n>`, the n-th newest backup of `path`.
+fn backup_path(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{n}"));
+    PathBuf::from(name)
+}
+
+/// Columns (name, type) added to `repos` after it was first created, added on
+/// open to databases that predate them.
+const ADDED_COLUMNS: &[(&str, &str)] = &[
+    ("commits_etag", "TEXT"),
+    ("runs_etag", "TEXT"),
+    ("conclusion", "TEXT"),
+    ("consecutive_failures", "INTEGER NOT NULL DEFAULT 0"),
+    ("last_error", "TEXT"),
+    ("last_error_at", "TEXT"),
+    ("queued", "TEXT"),
+    ("prereleases", "TEXT"),
+    ("held", "TEXT"),
+];
+
+/// One row per repo in a `repos` table, upserted as soon as anything changes.
+pub struct SqliteStore {
+    conn: Connection,
+}
+
+impl SqliteStore {
+    pub fn open(path: &Path) -> Result<Self> {
+        let conn = Connection::open(path)
+            .with_context(|| format!("opening state db {}", path.display()))?;
+        Self::init(conn)
+    }
+    fn init(conn: Connection) -> Result<Self> {
+        // WAL + a busy timeout let a second instance wait instead of failing
+        conn.busy_timeout(Duration::from_secs(5))?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS repos (
+                name TEXT PRIMARY KEY,
+                last_tag TEXT,
+                updated_at TEXT,
+                kind TEXT,
+                last_checked_at TEXT,
+                last_notified_at TEXT,
+                releases_etag TEXT,
+                tags_etag TEXT,
+                commits_etag TEXT,
+                runs_etag TEXT,
+                conclusion TEXT,
+                consecutive_failures INTEGER NOT NULL DEFAULT 0,
+                last_error TEXT,
+                last_error_at TEXT,
+                queued TEXT,
+                prereleases TEXT,
+                held TEXT
+            )",
+        )
+        .context("creating state schema")?;
+        for (column, ty) in ADDED_COLUMNS {
+            let exists: bool = conn.query_row(
+                "SELECT EXISTS (SELECT 1 FROM pragma_table_info('repos') WHERE name = ?1)",
+                [column],
+                |row| row.get(0),
+            )?;
+            if !exists {
+                conn.execute_batch(&format!("ALTER TABLE repos ADD COLUMN {column} {ty}"))
+                    .with_context(|| format!("adding state column {column}"))?;
+            }
+        }
+        Ok(Self { conn })
+    }
+}
+
+/// Columns read by [`repo_state_from_row`], in order.
+const REPO_STATE_COLUMNS: &str = "last_tag, last_checked_at, last_notified_at, kind, conclusion, \
+    consecutive_failures, last_error, last_error_at, queued, prereleases, held";
+
+fn repo_state_from_row(row: &rusqlite::Row, first: usize) -> rusqlite::Result<RepoState> {
+    Ok(RepoState {
+        last_seen_tag: row.get(first)?,
+        last_checked_at: row.get(first + 1)?,
+        last_notified_at: row.get(first + 2)?,
+        kind: row.get::<_, Option<String>>(first + 3)?.as_deref().and_then(parse_kind),
+        conclusion: row.get(first + 4)?,
+        consecutive_failures: row.get(first + 5)?,
+        last_error: row.get(first + 6)?,
+        last_error_at: row.get(first + 7)?,
+        queued: json_column(row, first + 8)?,
+        prereleases: json_column(row, first + 9)?,
+        held: json_column(row, first + 10)?,
+    })
+}
+
+/// A column holding JSON, NULL when the value is empty.
+fn json_column<T>(row: &rusqlite::Row, i: usize) -> rusqlite::Result<T>
+where
+    T: DeserializeOwned + Default,
+{
+    match row.get::<_, Option<String>>(i)? {
+        Some(json) => serde_json::from_str(&json).map_err(|e| {
+            rusqlite::Error::FromSqlConversionFailure(i, rusqlite::types::Type::Text, Box::new(e))
+        }),
+        None => Ok(T::default()),
+    }
+}
+
+impl StateStore for SqliteStore {
+    fn get(&self, repo: &str) -> Result<Option<RepoState>> {
+        let sql = format!("SELECT {REPO_STATE_COLUMNS} FROM repos WHERE name = ?1");
+        Ok(self
+            .conn
+            .query_row(&sql, [repo], |row| repo_state_from_row(row, 0))
+            .optional()?)
+    }
+    fn set(&mut self, repo: &str, state: &RepoState) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO repos
+                (name, last_tag, updated_at, kind, last_checked_at, last_notified_at, conclusion,
+                 consecutive_failures, last_error, last_error_at, queued, prereleases, held)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)
+             ON CONFLICT(name) DO UPDATE SET
+                last_tag = excluded.last_tag,
+                updated_at = excluded.updated_at,
+                kind = excluded.kind,
+                last_checked_at = excluded.last_checked_at,
+                last_notified_at = excluded.last_notified_at,
+                conclusion = excluded.conclusion,
+                consecutive_failures = excluded.consecutive_failures,
+                last_error = excluded.last_error,
+                last_error_at = excluded.last_error_at,
+                queued = excluded.queued,
+                prereleases = excluded.prereleases,
+                held = excluded.held",
+            params![
+                repo,
+                state.last_seen_tag,
+                Utc::now(),
+                state.kind.map(kind_name),
+                state.last_checked_at,
+                state.last_notified_at,
+                state.conclusion,
+                state.consecutive_failures,
+                state.last_error,
+                state.last_error_at,
+                (!state.queued.is_empty())
+                    .then(|| serde_json::to_string(&state.queued).expect("queue serializes")),
+                (!state.prereleases.is_empty()).then(|| {
+                    serde_json::to_string(&state.prereleases).expect("tags serialize")
+                }),
+                state
+                    .held
+                    .as_ref()
+                    .map(|held| serde_json::to_string(held).expect("detection serializes")),
+            ],
+        )?;
+        Ok(())
+    }
+    fn etag(&self, repo: &str, kind: Kind) -> Result<Option<String>> {
+        let sql = format!("SELECT {}_etag FROM repos WHERE name = ?1", endpoint(kind));
+        let etag = self
+            .conn
+            .query_row(&sql, [repo], |row| row.get::<_, Option<String>>(0))
+            .optional()?;
+        Ok(etag.flatten())
+    }
+    fn set_etag(&mut self, repo: &str, kind: Kind, etag: &str) -> Result<()> {
+        let column = format!("{}_etag", endpoint(kind));
+        let sql = format!(
+            "INSERT INTO repos (name, {column}, updated_at) VALUES (?1, ?2, ?3)
+             ON CONFLICT(name) DO UPDATE SET {column} = excluded.{column}, updated_at = excluded.updated_at"
+        );
+        self.conn.execute(&sql, params![repo, etag, Utc::now()])?;
+        Ok(())
+    }
+    fn remove(&mut self, repo: &str) -> Result<bool> {
+        Ok(self.conn.execute("DELETE FROM repos WHERE name = ?1", [repo])? > 0)
+    }
+    fn rename(&mut self, from: &str, to: &str) -> Result<bool> {
+        let moved = self.conn.execute(
+            "UPDATE repos SET name = ?2 WHERE name = ?1 \
+             AND NOT EXISTS (SELECT 1 FROM repos WHERE name = ?2)",
+            [from, to],
+        )?;
+        Ok(moved > 0)
+    }
+    fn clear(&mut self) -> Result<usize> {
+        Ok(self.conn.execute("DELETE FROM repos", [])?)
+    }
+    fn list(&self) -> Result<Vec<(String, RepoState)>> {
+        let sql = format!("SELECT name, {REPO_STATE_COLUMNS} FROM repos ORDER BY name");
+        let mut stmt = self.conn.prepare(&sql)?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, repo_state_from_row(row, 1)?)))?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+    fn has_seen_tags(&self) -> Result<bool> {
+        Ok(self.conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM repos WHERE last_tag IS NOT NULL)",
+            [],
+            |row| row.get(0),
+        )?)
+    }
+    fn flush(&mut self) -> Result<()> {
+        // every write above is already committed
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_state_upgrades_legacy_last_seen_map() {
+        let old: JsonState = serde_json::from_str(r#"{"last_seen":{"o/r":"v1.0.0"}}"#).unwrap();
+        assert_eq!(old.repos["o/r"].last_seen_tag.as_deref(), Some("v1.0.0"));
+        assert_eq!(old.repos["o/r"].last_checked_at, None);
+
+        let current: JsonState =
+            serde_json::from_str(&serde_json::to_string(&old).unwrap()).unwrap();
+        assert_eq!(current.repos, old.repos);
+    }
+
+    #[test]
+    fn lock_refuses_a_second_holder() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+
+        let held = lock(StateBackend::Json, &path).unwrap();
+        assert!(held.is_some());
+        let err = lock(StateBackend::Json, &path).err().unwrap();
+        assert!(err.to_string().contains("another instance"));
+        drop(held);
+        assert!(lock(StateBackend::Json, &path).unwrap().is_some());
+        assert!(lock(StateBackend::Sqlite, &path).unwrap().is_none());
+    }
+
+    #[test]
+    fn json_store_rotates_and_recovers_backups() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+
+        let mut store = JsonStore::open(&path, 2).unwrap();
+        for tag in ["v1", "v2", "v3"] {
+            store
+                .update("o/r", &mut |s| s.last_seen_tag = Some(tag.into()))
+                .unwrap();
+            store.flush().unwrap();
+        }
+        assert!(backup_path(&path, 2).exists());
+        assert!(!backup_path(&path, 3).exists());
+
+        let first = fs::read(backup_path(&path, 1)).unwrap();
+        for _ in 0..3 {
+            store.flush().unwrap();
+        }
+        assert_eq!(fs::read(backup_path(&path, 1)).unwrap(), first);
+
+        fs::write(&path, "{ not json").unwrap();
+        let store = JsonStore::open(&path, 2).unwrap();
+        assert_eq!(store.get("o/r").unwrap().unwrap().last_seen_tag.as_deref(), Some("v2"));
+
+        for n in 1..=2 {
+            fs::write(backup_path(&path, n), "").unwrap();
+        }
+        assert!(JsonStore::open(&path, 2).is_err());
+    }
+
+    #[test]
+    fn sqlite_store_round_trips() {
+        let mut store = SqliteStore::init(Connection::open_in_memory().unwrap()).unwrap();
+        assert!(!store.has_seen_tags().unwrap());
+
+        store.set_etag("o/r", Kind::Release, "\"abc\"").unwrap();
+        assert_eq!(store.get("o/r").unwrap(), Some(RepoState::default()));
+        assert!(!store.has_seen_tags().unwrap());
+
+        let detection = QueuedDetection {
+            tag: "v1.1.0".into(),
+            kind: Kind::Release,
+            previous: Some("v1.0.0".into()),
+            title: None,
+            url: None,
+            commit_sha: None,
+            notes: Some("fixes".into()),
+            release_name: Some("Hydrogen".into()),
+            initial: false,
+            prerelease: false,
+            promoted_from: None,
+            verified: None,
+        };
+        let state = RepoState {
+            last_seen_tag: Some("v1.0.0".into()),
+            last_checked_at: Some(Utc::now()),
+            last_notified_at: None,
+            kind: Some(Kind::Release),
+            conclusion: None,
+            consecutive_failures: 2,
+            last_error: Some("listing releases: Not Found".into()),
+            last_error_at: Some(Utc::now()),
+            queued: vec![detection.clone()],
+            prereleases: vec!["v1.1.0-rc.1".into()],
+            held: Some(detection),
+        };
+        store.set("o/r", &state).unwrap();
+        assert_eq!(store.get("o/r").unwrap(), Some(state));
+        assert_eq!(store.etag("o/r", Kind::Release).unwrap().as_deref(), Some("\"abc\""));
+        assert_eq!(store.etag("o/r", Kind::Tag).unwrap(), None);
+        assert!(store.has_seen_tags().unwrap());
+
+        store.set("a/b", &RepoState::default()).unwrap();
+        let names: Vec<_> = store.list().unwrap().into_iter().map(|(name, _)| name).collect();
+        assert_eq!(names, ["a/b", "o/r"]);
+
+        assert!(!store.rename("o/r", "a/b").unwrap());
+        assert!(store.rename("o/r", "n/r").unwrap());
+        assert_eq!(store.get("o/r").unwrap(), None);
+        assert_eq!(store.etag("n/r", Kind::Release).unwrap().as_deref(), Some("\"abc\""));
+        assert!(store.rename("n/r", "o/r").unwrap());
+
+        assert!(store.remove("o/r").unwrap());
+        assert!(!store.remove("o/r").unwrap());
+        assert_eq!(store.etag("o/r", Kind::Release).unwrap(), None);
+        assert_eq!(store.clear().unwrap(), 1);
+        assert!(store.list().unwrap().is_empty());
+    }
+}